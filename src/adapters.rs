@@ -0,0 +1,311 @@
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use keystream::{Keystream, check_key};
+
+/// The largest number of bytes an `XorWriter` encrypts per call to `write`.
+const WRITE_CHUNK_SIZE : usize = 8 * 1024;
+
+/// Wraps a reader and XOR's everything read through it against the key.
+///
+/// The key position follows the bytes returned, so any reader can be stacked on top of it, such
+/// as a `BufReader`, regardless of how it splits up reads.
+pub struct XorReader<'a, R> {
+    inner : R,
+    keystream : Keystream<'a>
+}
+
+impl<'a, R: Read> XorReader<'a, R> {
+
+    /// Creates a reader that starts at the beginning of the key.
+    ///
+    /// # Panics
+    ///
+    /// If the key is empty, see `keystream::check_key`.
+    pub fn new(inner : R, key : &'a [u8]) -> XorReader<'a, R> {
+        XorReader::with_offset(inner, key, 0)
+    }
+
+    /// Creates a reader whose first byte is XOR'd against the key byte for "offset".
+    ///
+    /// # Panics
+    ///
+    /// If the key is empty, see `keystream::check_key`.
+    pub fn with_offset(inner : R, key : &'a [u8], offset : u64) -> XorReader<'a, R> {
+        XorReader {
+            inner,
+            keystream : Keystream::with_offset(key, offset)
+        }
+    }
+
+    /// The key offset that will be used for the next byte read.
+    pub fn offset(&self) -> u64 {
+        self.keystream.offset()
+    }
+
+    /// Gets a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Gets a mutable reference to the underlying reader, reading or writing through it directly
+    /// bypasses the key.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// Unwraps this adapter, returning the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<'a, R: Read> Read for XorReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.keystream.apply(&mut buf[..n]);
+        Ok(n)
+    }
+}
+
+/// Wraps a writer and XOR's everything written through it against the key.
+///
+/// The key position only moves past bytes the inner writer actually accepted, so short writes
+/// never skip key bytes.
+pub struct XorWriter<'a, W> {
+    inner : W,
+    keystream : Keystream<'a>,
+    buffer : Vec<u8>
+}
+
+impl<'a, W: Write> XorWriter<'a, W> {
+
+    /// Creates a writer that starts at the beginning of the key.
+    ///
+    /// # Panics
+    ///
+    /// If the key is empty, see `keystream::check_key`.
+    pub fn new(inner : W, key : &'a [u8]) -> XorWriter<'a, W> {
+        XorWriter::with_offset(inner, key, 0)
+    }
+
+    /// Creates a writer whose first byte is XOR'd against the key byte for "offset".
+    ///
+    /// # Panics
+    ///
+    /// If the key is empty, see `keystream::check_key`.
+    pub fn with_offset(inner : W, key : &'a [u8], offset : u64) -> XorWriter<'a, W> {
+        XorWriter {
+            inner,
+            keystream : Keystream::with_offset(key, offset),
+            buffer : Vec::new()
+        }
+    }
+
+    /// The key offset that will be used for the next byte written.
+    pub fn offset(&self) -> u64 {
+        self.keystream.offset()
+    }
+
+    /// Gets a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Gets a mutable reference to the underlying writer, reading or writing through it directly
+    /// bypasses the key.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Unwraps this adapter, returning the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<'a, W: Write> Write for XorWriter<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        write_xored(&mut self.inner, &mut self.keystream, &mut self.buffer, buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Wraps a seekable stream, such as a file, and XOR's everything read from or written to it.
+///
+/// The key offset is always the absolute position in the stream, so seeking to byte N then
+/// reading or writing uses the same key bytes as streaming from the start would. This allows
+/// decrypting a record from the middle of a file, or patching a region of an encrypted file in
+/// place, without touching the rest of it.
+pub struct XorStream<'a, S> {
+    inner : S,
+    keystream : Keystream<'a>,
+    buffer : Vec<u8>
+}
+
+impl<'a, S: Seek> XorStream<'a, S> {
+
+    /// Creates a stream whose key offset matches the current position of "inner".
+    /// An empty key is an `InvalidInput` error.
+    pub fn new(mut inner : S, key : &'a [u8]) -> io::Result<XorStream<'a, S>> {
+        check_key(key)?;
+        let position = inner.stream_position()?;
+
+        Ok(XorStream {
+            inner,
+            keystream : Keystream::with_offset(key, position),
+            buffer : Vec::new()
+        })
+    }
+
+    /// The absolute position, and so key offset, of the next byte read or written.
+    pub fn offset(&self) -> u64 {
+        self.keystream.offset()
+    }
+
+    /// Gets a reference to the underlying stream.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Unwraps this adapter, returning the underlying stream.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<'a, S: Read + Seek> Read for XorStream<'a, S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.keystream.apply(&mut buf[..n]);
+        Ok(n)
+    }
+}
+
+impl<'a, S: Write + Seek> Write for XorStream<'a, S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        write_xored(&mut self.inner, &mut self.keystream, &mut self.buffer, buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<'a, S: Seek> Seek for XorStream<'a, S> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = self.inner.seek(pos)?;
+        self.keystream.set_offset(position);
+        Ok(position)
+    }
+}
+
+/// Encrypts a copy of the start of "buf" and writes it, moving the keystream past only the bytes
+/// the writer accepted.
+fn write_xored<W: Write>(inner : &mut W, keystream : &mut Keystream, buffer : &mut Vec<u8>, buf : &[u8]) -> io::Result<usize> {
+    let len = buf.len().min(WRITE_CHUNK_SIZE);
+    let start = keystream.offset();
+
+    buffer.clear();
+    buffer.extend_from_slice(&buf[..len]);
+    keystream.apply(buffer);
+
+    match inner.write(buffer) {
+        Ok(n) => {
+            keystream.set_offset(start + n as u64);
+            Ok(n)
+        },
+        Err(e) => {
+            keystream.set_offset(start);
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufReader, Cursor};
+
+    /// Writer that accepts at most three bytes per write.
+    struct ShortWriter(Vec<u8>);
+
+    impl Write for ShortWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let n = buf.len().min(3);
+            self.0.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn reader_and_writer_round_trip() {
+        let key = b"secret key";
+        let plaintext : Vec<u8> = (0..100).collect();
+
+        let mut writer = XorWriter::new(ShortWriter(Vec::new()), key);
+        writer.write_all(&plaintext).unwrap();
+        assert_eq!(writer.offset(), 100);
+
+        let ciphertext = writer.into_inner().0;
+        let mut expected = plaintext.clone();
+        Keystream::new(key).apply(&mut expected);
+        assert_eq!(ciphertext, expected);
+
+        let mut reader = BufReader::with_capacity(7, XorReader::new(Cursor::new(ciphertext), key));
+        let mut decrypted = Vec::new();
+        reader.read_to_end(&mut decrypted).unwrap();
+        assert_eq!(decrypted, plaintext);
+    }
+
+    #[test]
+    fn reader_starts_at_the_given_offset() {
+        let key = [1_u8, 2_u8, 3_u8];
+        let mut reader = XorReader::with_offset(Cursor::new(vec![0_u8; 4]), &key, 2);
+
+        let mut output = Vec::new();
+        reader.read_to_end(&mut output).unwrap();
+
+        assert_eq!(output, vec![3, 1, 2, 3]);
+        assert_eq!(reader.offset(), 6);
+    }
+
+    #[test]
+    fn stream_reads_from_the_middle() {
+        let key = b"0123456789abcdef";
+        let plaintext : Vec<u8> = (0..200).collect();
+        let mut ciphertext = plaintext.clone();
+        Keystream::new(key).apply(&mut ciphertext);
+
+        let mut stream = XorStream::new(Cursor::new(ciphertext), key).unwrap();
+        stream.seek(SeekFrom::Start(123)).unwrap();
+
+        let mut record = [0_u8; 10];
+        stream.read_exact(&mut record).unwrap();
+
+        assert_eq!(&record[..], &plaintext[123..133]);
+        assert_eq!(stream.offset(), 133);
+    }
+
+    #[test]
+    fn stream_patches_a_region_in_place() {
+        let key = b"key";
+        let mut plaintext = vec![b'a'; 50];
+        let mut ciphertext = plaintext.clone();
+        Keystream::new(key).apply(&mut ciphertext);
+
+        let mut stream = XorStream::new(Cursor::new(ciphertext), key).unwrap();
+        stream.seek(SeekFrom::End(-10)).unwrap();
+        stream.write_all(b"patched").unwrap();
+        plaintext[40..47].copy_from_slice(b"patched");
+
+        let mut decrypted = stream.into_inner().into_inner();
+        Keystream::new(key).apply(&mut decrypted);
+
+        assert_eq!(decrypted, plaintext);
+    }
+}
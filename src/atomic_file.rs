@@ -0,0 +1,284 @@
+use std::io::{self, Write, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use rsfs::*;
+use keystream::Keystream;
+use tree::SymlinkFn;
+
+/// The prefix given to temporary files that hold new file contents until they replace the
+/// original. A name starting with "." is never valid hex, so these can't be confused with
+/// encrypted names. They're left alone by a walk, and removed when a journal is resumed or
+/// rolled back, see `remove_leftover_temps`.
+pub const TEMP_FILE_PREFIX : &str = ".xor-tmp.";
+
+/// The number of bytes of a file that are held in memory at once while it's being XOR'd.
+pub const FILE_BLOCK_SIZE : usize = 64 * 1024;
+
+static TEMP_FILE_COUNTER : AtomicUsize = AtomicUsize::new(0);
+
+/// Returns true if the path names a temporary file left behind by an interrupted rewrite.
+/// Only names of the form `temp_path_for` gives, the prefix followed by a process id and a count,
+/// are matched, so a user's own file that happens to share the prefix is never taken for one.
+pub fn is_temp_file(path : &Path) -> bool {
+    let suffix = match path.file_name().and_then(|name| name.to_str()).and_then(|name| name.strip_prefix(TEMP_FILE_PREFIX)) {
+        Some(suffix) => suffix,
+        None => return false
+    };
+
+    let is_number = |part : &str| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit());
+    let mut parts = suffix.split('.');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(pid), Some(count), None) => is_number(pid) && is_number(count),
+        _ => false
+    }
+}
+
+/// Replaces the contents of a file with its contents XOR'd against the key, without ever leaving
+/// the file truncated or half written.
+///
+/// The new contents are written to a temporary file in the same directory which is synced to disk
+/// then renamed over the original, so after a crash the path holds either the old or the new
+/// contents. Copying the original first keeps its permissions on the replacement.
+pub fn rewrite_file<T: GenFS>(fs: &T, path : &Path, key : &[u8]) -> io::Result<()> {
+    rewrite(fs, path, key, false, |_| Ok(()))
+}
+
+/// The same as `rewrite_file`, but "before_replace" is called with the path of the completed
+/// temporary file just before it's renamed over the original, so it can be recorded in a journal.
+/// If it fails the original is left untouched. If the rename fails once it has succeeded the
+/// temporary file is left for the journal to clean up, see `remove_leftover_temps`.
+pub fn rewrite_file_then<T, F>(fs: &T, path : &Path, key : &[u8], before_replace : F) -> io::Result<()>
+    where T: GenFS, F: FnOnce(&Path) -> io::Result<()> {
+
+    rewrite(fs, path, key, true, before_replace)
+}
+
+fn rewrite<T, F>(fs: &T, path : &Path, key : &[u8], recorded : bool, before_replace : F) -> io::Result<()>
+    where T: GenFS, F: FnOnce(&Path) -> io::Result<()> {
+
+    let temp_path = temp_path_for(path);
+
+    fs.copy(path, &temp_path)?;
+
+    if let Err(e) = xor_file_in_place(fs, &temp_path, key) {
+        let _ = fs.remove_file(&temp_path);
+        return Err(e);
+    }
+
+    replace_with_temp(fs, &temp_path, path, recorded, before_replace)
+}
+
+/// Replaces the contents of a file with whatever "write" writes, without ever leaving the file
+/// truncated or half written, the same way as `rewrite_file`.
+///
+/// "write" is given the original file to read from and the temporary file to write to. If
+/// "backup" is given the original contents are copied there, and synced, before the file is
+/// replaced. A backup that already exists is never overwritten, the file is left alone instead.
+pub fn replace_file_with<T, F>(fs: &T, path : &Path, backup : Option<&Path>, write : F) -> io::Result<()>
+    where T: GenFS, T::Metadata: Metadata<Permissions = T::Permissions>,
+          F: FnOnce(T::File, &mut T::File) -> io::Result<()> {
+
+    let original = fs.open_file(path)?;
+    let permissions = fs.metadata(path)?.permissions();
+    let temp_path = temp_path_for(path);
+
+    let result = fs.create_file(&temp_path)
+        .and_then(|mut temp| {
+            fs.set_permissions(&temp_path, permissions)?;
+            write(original, &mut temp)?;
+            temp.flush()?;
+            temp.sync_all()
+        })
+        .and_then(|_| match backup {
+            Some(backup) => back_up(fs, path, backup),
+            None => Ok(())
+        })
+        .and_then(|_| fs.rename(&temp_path, path));
+
+    if result.is_err() {
+        let _ = fs.remove_file(&temp_path);
+    }
+
+    result
+}
+
+/// Copies "path" to "backup", which mustn't exist yet, and syncs the copy to disk.
+fn back_up<T: GenFS>(fs: &T, path : &Path, backup : &Path) -> io::Result<()> {
+    // Creating it first means an existing file is refused rather than overwritten.
+    fs.new_openopts().write(true).create_new(true).open(backup)?;
+
+    let result = fs.copy(path, backup)
+        .and_then(|_| fs.open_file(backup))
+        .and_then(|copy| copy.sync_all());
+
+    if result.is_err() {
+        let _ = fs.remove_file(backup);
+    }
+
+    result
+}
+
+/// Replaces "path" with a hard link to "existing", without ever leaving "path" missing.
+/// The link is made under a temporary name then renamed over "path".
+pub fn link_file<T: GenFS>(fs: &T, existing : &Path, path : &Path) -> io::Result<()> {
+    let temp_path = temp_path_for(path);
+    fs.hard_link(existing, &temp_path)?;
+    replace_with_temp(fs, &temp_path, path, false, |_| Ok(()))
+}
+
+/// The same as `link_file`, but "before_replace" is called with the temporary name just before
+/// the rename as with `rewrite_file_then`.
+pub fn link_file_then<T, F>(fs: &T, existing : &Path, path : &Path, before_replace : F) -> io::Result<()>
+    where T: GenFS, F: FnOnce(&Path) -> io::Result<()> {
+
+    let temp_path = temp_path_for(path);
+    fs.hard_link(existing, &temp_path)?;
+    replace_with_temp(fs, &temp_path, path, true, before_replace)
+}
+
+/// Replaces the symlink "path" with one pointing at "target", without ever leaving "path" missing.
+/// The new symlink is made under a temporary name then renamed over "path".
+pub fn replace_symlink<T: GenFS>(fs: &T, create_symlink : SymlinkFn<T>, target : &Path, path : &Path) -> io::Result<()> {
+    let temp_path = temp_path_for(path);
+    create_symlink(fs, target, &temp_path)?;
+    replace_with_temp(fs, &temp_path, path, false, |_| Ok(()))
+}
+
+/// The same as `replace_symlink`, but "before_replace" is called with the temporary name just
+/// before the rename as with `rewrite_file_then`.
+pub fn replace_symlink_then<T, F>(fs: &T, create_symlink : SymlinkFn<T>, target : &Path, path : &Path, before_replace : F) -> io::Result<()>
+    where T: GenFS, F: FnOnce(&Path) -> io::Result<()> {
+
+    let temp_path = temp_path_for(path);
+    create_symlink(fs, target, &temp_path)?;
+    replace_with_temp(fs, &temp_path, path, true, before_replace)
+}
+
+/// Calls "before_replace" with the completed temporary file then renames it over "path".
+/// The temporary file is removed if either fails, unless "recorded" is set and "before_replace"
+/// succeeded: a journal then refers to the temporary file, and removing it would make the change
+/// look finished.
+fn replace_with_temp<T, F>(fs: &T, temp_path : &Path, path : &Path, recorded : bool, before_replace : F) -> io::Result<()>
+    where T: GenFS, F: FnOnce(&Path) -> io::Result<()> {
+
+    if let Err(e) = before_replace(temp_path) {
+        let _ = fs.remove_file(temp_path);
+        return Err(e);
+    }
+
+    let result = fs.rename(temp_path, path);
+    if result.is_err() && !recorded {
+        let _ = fs.remove_file(temp_path);
+    }
+
+    result
+}
+
+/// XOR's a file in place a block at a time so memory use doesn't grow with the file size, then
+/// syncs it to disk.
+fn xor_file_in_place<T: GenFS>(fs: &T, path : &Path, key : &[u8]) -> io::Result<()> {
+    let mut file = fs.new_openopts()
+        .read(true)
+        .write(true)
+        .open(path)?;
+
+    let mut keystream = Keystream::new(key);
+    let mut block = vec![0_u8; FILE_BLOCK_SIZE];
+
+    loop {
+        let n = file.read(&mut block)?;
+        if n == 0 {
+            break;
+        }
+
+        // Step back over the block just read and overwrite it with the XOR'd bytes.
+        keystream.apply(&mut block[..n]);
+        file.seek(SeekFrom::Current(-(n as i64)))?;
+        file.write_all(&block[..n])?;
+    }
+    file.flush()?;
+    file.sync_all()?;
+    debug!("XOR'd {} bytes of {:?}", keystream.offset(), path);
+
+    Ok(())
+}
+
+fn temp_path_for(path : &Path) -> PathBuf {
+    let count = TEMP_FILE_COUNTER.fetch_add(1, Ordering::SeqCst);
+    let temp_name = format!("{}{}.{}", TEMP_FILE_PREFIX, process::id(), count);
+
+    match path.parent() {
+        Some(parent) => parent.join(temp_name),
+        None => PathBuf::from(temp_name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rsfs::mem::FS;
+
+    #[test]
+    fn rewrite_file_leaves_no_temporary_files() {
+        let fs = FS::new();
+        fs.create_file("/data").unwrap().write_all(b"hello").unwrap();
+
+        rewrite_file(&fs, Path::new("/data"), &[57]).unwrap();
+
+        let names : Vec<PathBuf> = fs.read_dir("/").unwrap().map(|e| e.unwrap().path()).collect();
+        assert_eq!(names, vec![PathBuf::from("/data")]);
+
+        let mut contents = Vec::new();
+        fs.open_file("/data").unwrap().read_to_end(&mut contents).unwrap();
+        assert_eq!(contents, b"Q\\UUV");
+    }
+
+    #[test]
+    fn replace_file_with_keeps_a_backup_and_the_original_on_failure() {
+        let fs = FS::new();
+        fs.create_file("/data").unwrap().write_all(b"hello").unwrap();
+
+        replace_file_with(&fs, Path::new("/data"), Some(Path::new("/data.bak")), |mut original, temp| {
+            let mut contents = Vec::new();
+            original.read_to_end(&mut contents)?;
+            contents.reverse();
+            temp.write_all(&contents)
+        }).unwrap();
+
+        let read = |path| {
+            let mut contents = Vec::new();
+            fs.open_file(path).unwrap().read_to_end(&mut contents).unwrap();
+            contents
+        };
+        assert_eq!(read("/data"), b"olleh");
+        assert_eq!(read("/data.bak"), b"hello");
+
+        let err = replace_file_with(&fs, Path::new("/data"), Some(Path::new("/data.bak")), |_, temp| {
+            temp.write_all(b"lost")
+        }).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(read("/data"), b"olleh");
+        assert_eq!(read("/data.bak"), b"hello");
+
+        let err = replace_file_with(&fs, Path::new("/data"), None, |_, temp| {
+            temp.write_all(b"partial")?;
+            Err(io::Error::new(io::ErrorKind::InvalidData, "bad input"))
+        }).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(read("/data"), b"olleh");
+
+        let mut names : Vec<PathBuf> = fs.read_dir("/").unwrap().map(|e| e.unwrap().path()).collect();
+        names.sort();
+        assert_eq!(names, vec![PathBuf::from("/data"), PathBuf::from("/data.bak")]);
+    }
+
+    #[test]
+    fn temp_files_are_recognised() {
+        assert!(is_temp_file(&temp_path_for(Path::new("/dir/file"))));
+        assert!(!is_temp_file(Path::new("/dir/2E2937")));
+        assert!(!is_temp_file(Path::new("/dir/.xor-tmp.notes")));
+        assert!(!is_temp_file(Path::new("/dir/.xor-tmp.12.3.bak")));
+        assert!(!is_temp_file(Path::new("/dir/.xor-tmp.12.")));
+    }
+}
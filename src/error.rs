@@ -0,0 +1,143 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// What was being done to a path when a run failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operation {
+    /// Checking the tree's marker before starting.
+    CheckMarker,
+    /// Starting the journal at the root of the tree.
+    StartJournal,
+    /// Reading the journal of an interrupted run.
+    ReadJournal,
+    /// Reading the name manifest before decrypting.
+    ReadManifest,
+    /// Recording a change in the journal before making it.
+    RecordChange,
+    /// Listing the entries of a directory.
+    ReadDir,
+    /// Reading the ignore file of a directory.
+    ReadIgnoreFile,
+    /// XORing the contents of a file.
+    Xor,
+    /// Replacing a hard link with a link to XOR'd contents.
+    Link,
+    /// Renaming an entry.
+    Rename,
+    /// Rewriting the target of a symlink.
+    RewriteTarget,
+    /// Following a symlink.
+    Follow,
+    /// Removing the temporary files left by an interrupted run.
+    RemoveTemp,
+    /// Marking or unmarking the tree and removing the journal once the walk is done.
+    Finish,
+    /// Undoing an interrupted run.
+    RollBack,
+    /// Opening a file to read from.
+    Open,
+    /// Creating a file to write to.
+    Create,
+    /// Reading, XORing and writing a stream.
+    Encrypt
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match *self {
+            Operation::CheckMarker => "check the marker of",
+            Operation::StartJournal => "start a journal in",
+            Operation::ReadJournal => "read the journal in",
+            Operation::ReadManifest => "read the name manifest in",
+            Operation::RecordChange => "record the change to",
+            Operation::ReadDir => "read the directory",
+            Operation::ReadIgnoreFile => "read the ignore file in",
+            Operation::Xor => "XOR the contents of",
+            Operation::Link => "link",
+            Operation::Rename => "rename",
+            Operation::RewriteTarget => "rewrite the target of",
+            Operation::Follow => "follow",
+            Operation::RemoveTemp => "remove the leftover temporary files in",
+            Operation::Finish => "finish the run in",
+            Operation::RollBack => "roll back the run in",
+            Operation::Open => "open",
+            Operation::Create => "create",
+            Operation::Encrypt => "encrypt"
+        };
+        write!(f, "{}", description)
+    }
+}
+
+/// Why part of a run failed, naming the path involved and what was being done to it.
+#[derive(Debug)]
+pub enum XorError {
+    /// An operation on a path failed.
+    Io { operation : Operation, path : PathBuf, error : io::Error },
+    /// Decrypting turned up a name or manifest that the key the tree was encrypted with would never
+    /// give, so the key is almost certainly a different one.
+    KeyMismatch { path : PathBuf, reason : String }
+}
+
+impl XorError {
+
+    /// An operation on "path" failed with "error".
+    pub fn io(operation : Operation, path : &Path, error : io::Error) -> XorError {
+        XorError::Io { operation, path : path.to_path_buf(), error }
+    }
+
+    /// The path that was being processed.
+    pub fn path(&self) -> &Path {
+        match *self {
+            XorError::Io { ref path, .. } | XorError::KeyMismatch { ref path, .. } => path
+        }
+    }
+
+    /// The kind of the underlying `io::Error`. A mismatched key counts as invalid data.
+    pub fn kind(&self) -> io::ErrorKind {
+        match *self {
+            XorError::Io { ref error, .. } => error.kind(),
+            XorError::KeyMismatch { .. } => io::ErrorKind::InvalidData
+        }
+    }
+
+    /// Returns true if the failure is down to using the wrong key.
+    pub fn is_key_mismatch(&self) -> bool {
+        match *self {
+            XorError::KeyMismatch { .. } => true,
+            XorError::Io { .. } => false
+        }
+    }
+}
+
+impl fmt::Display for XorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            XorError::Io { operation, ref path, ref error } => write!(f, "failed to {} {:?} because: {}", operation, path, error),
+            XorError::KeyMismatch { ref path, ref reason } => write!(f, "the key doesn't match {:?}: {}", path, reason)
+        }
+    }
+}
+
+impl Error for XorError {
+    fn description(&self) -> &str {
+        match *self {
+            XorError::Io { .. } => "an operation on a path failed",
+            XorError::KeyMismatch { .. } => "the key doesn't match"
+        }
+    }
+
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            XorError::Io { ref error, .. } => Some(error),
+            XorError::KeyMismatch { .. } => None
+        }
+    }
+}
+
+impl From<XorError> for io::Error {
+    fn from(error : XorError) -> io::Error {
+        io::Error::new(error.kind(), error)
+    }
+}
@@ -0,0 +1,411 @@
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use rsfs::*;
+
+/// The name of the files that list, gitignore style, the entries a recursive run leaves alone.
+/// The rules in one apply to the directory it's in and everything below it.
+pub const IGNORE_FILE_NAME : &str = ".xorignore";
+
+/// Returns true if the path names an ignore file.
+pub fn is_ignore_file(path : &Path) -> bool {
+    path.file_name().map(|name| name == IGNORE_FILE_NAME).unwrap_or(false)
+}
+
+/// A gitignore style glob.
+///
+/// "*" matches any run of characters and "?" any single character, except "/". "[abc]",
+/// "[a-z]" and "[!abc]" match one character from, or not from, a set, and "\" makes the next
+/// character literal. A "**" component matches any number of directories.
+///
+/// A pattern without a "/" matches the name of an entry at any depth, otherwise it's matched
+/// against the whole path from the directory it applies to, and a leading "/" only anchors it.
+/// A trailing "/" means only directories match.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Pattern {
+    text : String,
+    components : Vec<Vec<char>>,
+    anchored : bool,
+    dir_only : bool
+}
+
+impl Pattern {
+
+    /// Returns true if the entry with the given path, relative to the directory the pattern
+    /// applies to, matches.
+    pub fn matches(&self, path : &[String], is_dir : bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+
+        if self.anchored {
+            let path : Vec<Vec<char>> = path.iter().map(|name| name.chars().collect()).collect();
+            components_match(&self.components, &path)
+        } else {
+            match path.last() {
+                Some(name) => wildcard_match(&self.components[0], &name.chars().collect::<Vec<char>>()),
+                None => false
+            }
+        }
+    }
+}
+
+impl FromStr for Pattern {
+    type Err = String;
+
+    fn from_str(s : &str) -> Result<Pattern, String> {
+        let mut text = s;
+
+        let dir_only = text.ends_with('/') && !text.ends_with("\\/");
+        if dir_only {
+            text = &text[..text.len() - 1];
+        }
+
+        let anchored = text.contains('/');
+        let components : Vec<Vec<char>> = text
+            .split('/')
+            .filter(|component| !component.is_empty())
+            .map(|component| component.chars().collect())
+            .collect();
+
+        if components.is_empty() {
+            return Err(format!("\"{}\" isn't a glob, it doesn't match any name", s));
+        }
+
+        for component in &components {
+            check_component(component).map_err(|reason| format!("\"{}\" isn't a valid glob, {}", s, reason))?;
+        }
+
+        Ok(Pattern { text : s.to_string(), components, anchored, dir_only })
+    }
+}
+
+/// The rules read from an ignore file, see `IGNORE_FILE_NAME`.
+///
+/// Blank lines and lines starting with "#" are ignored. Every other line is a `Pattern`, and a
+/// line starting with "!" brings back entries an earlier line left out. When several lines match
+/// an entry the last one wins.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IgnoreFile {
+    path : PathBuf,
+    rules : Vec<(Pattern, bool)>
+}
+
+impl IgnoreFile {
+
+    /// Reads the ignore file in "dir", returning None if there isn't one.
+    pub fn read<T: GenFS>(fs: &T, dir : &Path) -> io::Result<Option<IgnoreFile>> {
+        let path = dir.join(IGNORE_FILE_NAME);
+
+        let mut contents = String::new();
+        match fs.open_file(&path) {
+            Ok(mut file) => { file.read_to_string(&mut contents)?; },
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e)
+        }
+
+        Ok(Some(IgnoreFile::parse(path, &contents)))
+    }
+
+    /// Parses the contents of an ignore file. Lines that aren't valid globs are reported and
+    /// left out.
+    pub fn parse(path : PathBuf, contents : &str) -> IgnoreFile {
+        let mut rules = Vec::new();
+
+        for line in contents.lines() {
+            // Trailing whitespace is dropped unless it's escaped.
+            let line = line.trim_end_matches('\r');
+            let mut rule = line.trim_end();
+            if rule.ends_with('\\') && rule.len() < line.len() {
+                rule = &line[..rule.len() + 1];
+            }
+
+            if rule.is_empty() || rule.starts_with('#') {
+                continue;
+            }
+
+            let (rule, negated) = match rule.strip_prefix('!') {
+                Some(rule) => (rule, true),
+                None => (rule, false)
+            };
+
+            match rule.parse() {
+                Ok(pattern) => rules.push((pattern, negated)),
+                Err(e) => error!("Ignoring a line of {:?} because: {}", path, e)
+            }
+        }
+
+        IgnoreFile { path, rules }
+    }
+
+    /// Returns Some(true) if the entry is left out by the last matching line, Some(false) if it's
+    /// brought back by a "!" line, or None if no line matches.
+    pub fn matches(&self, path : &[String], is_dir : bool) -> Option<bool> {
+        self.rules
+            .iter()
+            .rev()
+            .find(|(pattern, _)| pattern.matches(path, is_dir))
+            .map(|&(_, negated)| !negated)
+    }
+}
+
+/// Whether a recursive run processes an entry, see `Filter::select`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Selection {
+    /// The entry is processed.
+    Selected,
+    /// The entry, and everything below it, is left alone for the given reason.
+    Excluded(String),
+    /// Include patterns were given and none of them match the entry or the directories above it.
+    /// Directories are still walked so the entries in them that match can be processed.
+    NotIncluded
+}
+
+/// Decides which entries a recursive run processes, from the patterns given with `include`
+/// and `exclude` and the ignore files found in the tree.
+///
+/// Paths are matched by name, as the entries are named before encrypting, so a filter selects
+/// the same entries in both directions. The walk tells the filter which directory it's in with
+/// `enter_dir` and `leave_dir` along with the directory's plain name.
+#[derive(Clone, Debug, Default)]
+pub struct Filter {
+    includes : Vec<Pattern>,
+    excludes : Vec<Pattern>,
+    dirs : Vec<FilterDir>
+}
+
+#[derive(Clone, Debug)]
+struct FilterDir {
+    name : Option<String>,
+    ignore_file : Option<IgnoreFile>,
+    included : bool
+}
+
+impl Filter {
+
+    /// Creates a filter that selects every entry, apart from those in ignore files.
+    pub fn new() -> Filter {
+        Filter::default()
+    }
+
+    /// Only processes the entries matching this pattern, or one of the other include patterns,
+    /// along with everything below the directories that match. The pattern is relative to the
+    /// root of the walk.
+    pub fn include(&mut self, pattern : Pattern) {
+        self.includes.push(pattern);
+    }
+
+    /// Leaves the entries matching this pattern alone, along with everything below them. The
+    /// pattern is relative to the root of the walk and wins over any include pattern.
+    pub fn exclude(&mut self, pattern : Pattern) {
+        self.excludes.push(pattern);
+    }
+
+    /// How many directories deep the walk is.
+    pub fn depth(&self) -> usize {
+        self.dirs.len()
+    }
+
+    /// Starts on the entries of "dir", reading its ignore file. "name" is the plain name of the
+    /// directory, or None for the root of the walk.
+    pub fn enter_dir<T: GenFS>(&mut self, fs: &T, dir : &Path, name : Option<String>) -> io::Result<()> {
+        let ignore_file = IgnoreFile::read(fs, dir)?;
+
+        let included = match (self.dirs.last(), &name) {
+            _ if self.includes.is_empty() => true,
+            (Some(parent), _) if parent.included => true,
+            (_, Some(name)) => {
+                let path = self.path_to(name);
+                self.includes.iter().any(|pattern| pattern.matches(&path, true))
+            },
+            (_, None) => false
+        };
+
+        self.dirs.push(FilterDir { name, ignore_file, included });
+        Ok(())
+    }
+
+    /// Finishes with the directory last passed to `enter_dir`.
+    pub fn leave_dir(&mut self) {
+        self.dirs.pop();
+    }
+
+    /// Whether the entry with the given plain name, in the current directory, is processed.
+    pub fn select(&self, name : &str, is_dir : bool) -> Selection {
+        let path = self.path_to(name);
+
+        if let Some(pattern) = self.excludes.iter().find(|pattern| pattern.matches(&path, is_dir)) {
+            return Selection::Excluded(format!("it matches the exclude pattern \"{}\"", pattern.text));
+        }
+
+        // Deeper ignore files win over the ones above them. The root of the walk isn't named, so
+        // the path from the directory at each depth starts that many components along.
+        let mut ignored_by = None;
+        for (depth, dir) in self.dirs.iter().enumerate() {
+            if let Some(ref ignore_file) = dir.ignore_file {
+                match ignore_file.matches(&path[depth..], is_dir) {
+                    Some(true) => ignored_by = Some(&ignore_file.path),
+                    Some(false) => ignored_by = None,
+                    None => ()
+                }
+            }
+        }
+        if let Some(ignore_file) = ignored_by {
+            return Selection::Excluded(format!("it's ignored by {:?}", ignore_file));
+        }
+
+        let included = self.includes.is_empty()
+            || self.dirs.last().map(|dir| dir.included).unwrap_or(false)
+            || self.includes.iter().any(|pattern| pattern.matches(&path, is_dir));
+
+        if included { Selection::Selected } else { Selection::NotIncluded }
+    }
+
+    /// The path of an entry in the current directory, relative to the root of the walk.
+    fn path_to(&self, name : &str) -> Vec<String> {
+        let mut path : Vec<String> = self.dirs.iter().filter_map(|dir| dir.name.clone()).collect();
+        path.push(name.to_string());
+        path
+    }
+}
+
+/// Matches the components of a pattern against the components of a path, "**" matching any
+/// number of them.
+fn components_match(pattern : &[Vec<char>], path : &[Vec<char>]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((first, rest)) if first.as_slice() == ['*', '*'] => (0..=path.len()).any(|skip| components_match(rest, &path[skip..])),
+        Some((first, rest)) => match path.split_first() {
+            Some((name, path)) => wildcard_match(first, name) && components_match(rest, path),
+            None => false
+        }
+    }
+}
+
+/// Matches a single component of a pattern against a single name.
+fn wildcard_match(pattern : &[char], name : &[char]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((&'*', rest)) => {
+            // A run of stars is the same as one, and matching it once keeps this from blowing up.
+            let rest = &rest[rest.iter().take_while(|&&c| c == '*').count()..];
+            (0..=name.len()).any(|skip| wildcard_match(rest, &name[skip..]))
+        },
+        Some((&'?', rest)) => !name.is_empty() && wildcard_match(rest, &name[1..]),
+        Some((&'[', rest)) => match (name.split_first(), class_match(rest, name.first().cloned())) {
+            (Some((_, name)), Some((true, len))) => wildcard_match(&rest[len..], name),
+            _ => false
+        },
+        Some((&'\\', rest)) if !rest.is_empty() => name.first() == Some(&rest[0]) && wildcard_match(&rest[1..], &name[1..]),
+        Some((&c, rest)) => name.first() == Some(&c) && wildcard_match(rest, &name[1..])
+    }
+}
+
+/// Matches a character against the set that starts just after a "[". Returns whether it matched
+/// and the length of the rest of the set, including the closing "]", or None if the set isn't
+/// closed.
+fn class_match(class : &[char], c : Option<char>) -> Option<(bool, usize)> {
+    let negated = class.first() == Some(&'!') || class.first() == Some(&'^');
+    let mut i = if negated { 1 } else { 0 };
+    let mut matched = false;
+    let mut first = true;
+
+    loop {
+        let mut low = match class.get(i) {
+            Some(&']') if !first => return Some((matched != negated, i + 1)),
+            Some(&low) => low,
+            None => return None
+        };
+        if low == '\\' {
+            i += 1;
+            low = *class.get(i)?;
+        }
+        i += 1;
+        first = false;
+
+        let mut high = low;
+        if class.get(i) == Some(&'-') && class.get(i + 1).map(|&c| c != ']').unwrap_or(false) {
+            high = class[i + 1];
+            if high == '\\' {
+                high = *class.get(i + 2)?;
+                i += 1;
+            }
+            i += 2;
+        }
+
+        if let Some(c) = c {
+            matched = matched || (low <= c && c <= high);
+        }
+    }
+}
+
+/// Checks that every set in a component is closed and that it doesn't end with a lone "\".
+fn check_component(component : &[char]) -> Result<(), &'static str> {
+    let mut i = 0;
+
+    while i < component.len() {
+        match component[i] {
+            '[' => match class_match(&component[i + 1..], None) {
+                Some((_, len)) => i += len,
+                None => return Err("a \"[\" isn't closed")
+            },
+            '\\' if i + 1 == component.len() => return Err("it ends with a \"\\\""),
+            '\\' => i += 1,
+            _ => ()
+        }
+        i += 1;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(path : &str) -> Vec<String> {
+        path.split('/').map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn patterns_match_like_gitignore() {
+        let matches = |pattern : &str, p : &str, is_dir : bool| pattern.parse::<Pattern>().unwrap().matches(&path(p), is_dir);
+
+        assert!(matches("*.swp", "a/b/.file.swp", false));
+        assert!(!matches("*.swp", "a/b.swp/c", false));
+        assert!(matches("build/", "src/build", true));
+        assert!(!matches("build/", "src/build", false));
+        assert!(matches("/target", "target", true));
+        assert!(!matches("/target", "sub/target", true));
+        assert!(matches("docs/*.md", "docs/a.md", false));
+        assert!(!matches("docs/*.md", "docs/sub/a.md", false));
+        assert!(matches("**/cache", "a/b/cache", true));
+        assert!(matches("a/**/z", "a/z", false));
+        assert!(matches("a/**/z", "a/b/c/z", false));
+        assert!(matches("file[0-9].[!t]xt", "file7.cxt", false));
+        assert!(!matches("file[0-9].[!t]xt", "file7.txt", false));
+        assert!(matches("\\*literal?", "*literal!", false));
+
+        assert!("[abc".parse::<Pattern>().is_err());
+        assert!("/".parse::<Pattern>().is_err());
+        assert!("trailing\\".parse::<Pattern>().is_err());
+    }
+
+    #[test]
+    fn deeper_ignore_files_and_negations_win() {
+        let root = IgnoreFile::parse(PathBuf::from("/root/.xorignore"), "# comment\n\n*.log\n.git/\n");
+        let sub = IgnoreFile::parse(PathBuf::from("/root/sub/.xorignore"), "!keep.log\n/local\n");
+
+        let mut filter = Filter::new();
+        filter.dirs.push(FilterDir { name : None, ignore_file : Some(root), included : true });
+        assert!(filter.select(".git", true) != Selection::Selected);
+        assert_eq!(filter.select(".git", false), Selection::Selected);
+        assert!(filter.select("keep.log", false) != Selection::Selected);
+        assert_eq!(filter.select("local", false), Selection::Selected);
+
+        filter.dirs.push(FilterDir { name : Some(String::from("sub")), ignore_file : Some(sub), included : true });
+        assert_eq!(filter.select("keep.log", false), Selection::Selected);
+        assert!(filter.select("other.log", false) != Selection::Selected);
+        assert!(filter.select("local", false) != Selection::Selected);
+    }
+}
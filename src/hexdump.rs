@@ -0,0 +1,209 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use keystream::{Keystream, check_key};
+
+/// The number of bytes shown on each line of a hexdump.
+pub const HEXDUMP_LINE_LEN : usize = 8;
+
+/// The first line of a hexdump written by `hexdump_reader`, so it can be told apart from an xxd
+/// dump when it's read back.
+pub const HEXDUMP_HEADER : &str = "# xor hexdump: offset, input, key, output, ascii of the output";
+
+/// XOR's everything read from "input" against the key and writes a hexdump of it to "output",
+/// returning the number of bytes read.
+///
+/// Each line shows the offset, then the input bytes, the key bytes applied to them and the
+/// output bytes in hex, then the output as ascii with anything unprintable shown as ".".
+/// An empty key is an `InvalidInput` error.
+pub fn hexdump_reader<R, W>(input : &mut R, key : &[u8], output : &mut W) -> io::Result<u64>
+    where R: Read + ?Sized, W: Write + ?Sized {
+
+        check_key(key)?;
+        let mut keystream = Keystream::new(key);
+        let mut line = [0; HEXDUMP_LINE_LEN];
+        let mut len = 0;
+
+        writeln!(output, "{}", HEXDUMP_HEADER)?;
+        loop {
+            match input.read(&mut line[len..]) {
+                Ok(0) => break,
+                Ok(n) => {
+                    len += n;
+                    if len == HEXDUMP_LINE_LEN {
+                        write_line(output, &mut keystream, &line)?;
+                        len = 0;
+                    }
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e)
+            }
+        }
+
+        if len > 0 {
+            write_line(output, &mut keystream, &line[..len])?;
+        }
+        output.flush()?;
+
+        Ok(keystream.offset())
+    }
+
+/// Writes one line of a hexdump for the input bytes in "line".
+fn write_line<W: Write + ?Sized>(output : &mut W, keystream : &mut Keystream, line : &[u8]) -> io::Result<()> {
+    let offset = keystream.offset();
+
+    // XORing zeros gives the key bytes themselves.
+    let mut key_bytes = vec![0; line.len()];
+    keystream.apply(&mut key_bytes);
+    let output_bytes : Vec<u8> = line.iter().zip(&key_bytes).map(|(byte, key_byte)| byte ^ key_byte).collect();
+
+    let ascii : String = output_bytes.iter().map(|&b| if b == b' ' || b.is_ascii_graphic() { b as char } else { '.' }).collect();
+    writeln!(output, "{:08x}:  {}  {}  {}  |{}|", offset, hex_column(line), hex_column(&key_bytes), hex_column(&output_bytes), ascii)
+}
+
+/// The bytes as space separated hex, padded to the width of a full line.
+fn hex_column(bytes : &[u8]) -> String {
+    let hex : Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{:width$}", hex.join(" "), width = HEXDUMP_LINE_LEN * 3 - 1)
+}
+
+/// Wraps a reader of a hexdump and reads the bytes it shows.
+///
+/// Both the lines written by `xxd` and by `hexdump_reader` are understood. For an xxd dump the hex
+/// before the ascii column is read, for one written by `hexdump_reader` it's the output column,
+/// so the output can be edited and XOR'd back again. Offsets aren't checked, the lines are read in
+/// the order they come, and blank lines and lines starting with "#" are skipped.
+pub struct HexdumpReader<R> {
+    inner : BufReader<R>,
+    own_format : bool,
+    line_number : usize,
+    decoded : Vec<u8>,
+    pos : usize
+}
+
+impl<R: Read> HexdumpReader<R> {
+
+    /// Creates a reader for the hexdump read from "inner".
+    pub fn new(inner : R) -> HexdumpReader<R> {
+        HexdumpReader {
+            inner : BufReader::new(inner),
+            own_format : false,
+            line_number : 0,
+            decoded : Vec::new(),
+            pos : 0
+        }
+    }
+
+    /// Decodes the bytes on one line of the dump into `decoded`.
+    fn parse_line(&mut self, line : &[u8]) -> io::Result<()> {
+        let line = String::from_utf8_lossy(line);
+        let line = line.trim_end();
+
+        if line.trim().is_empty() {
+            return Ok(());
+        }
+        if line.starts_with('#') {
+            if self.line_number == 1 && line == HEXDUMP_HEADER {
+                self.own_format = true;
+            }
+            return Ok(());
+        }
+
+        let colon = match line.find(':') {
+            Some(colon) if u64::from_str_radix(line[..colon].trim(), 16).is_ok() => colon,
+            _ => return Err(self.invalid("doesn't start with an offset"))
+        };
+        let rest = &line[colon + 1..];
+
+        let hex = if self.own_format {
+            let columns = rest.split('|').next().unwrap_or("");
+            let tokens : Vec<&str> = columns.split_whitespace().collect();
+            if !tokens.len().is_multiple_of(3) {
+                return Err(self.invalid("doesn't have the same number of input, key and output bytes"));
+            }
+            tokens[tokens.len() / 3 * 2..].concat()
+        } else {
+            // The hex ends where the two spaces before the ascii column start.
+            let rest = rest.strip_prefix(' ').unwrap_or(rest);
+            rest.split("  ").next().unwrap_or("").split_whitespace().collect::<Vec<&str>>().concat()
+        };
+
+        if hex.len() % 2 != 0 {
+            return Err(self.invalid("has an odd number of hex digits"));
+        }
+        for pair in hex.as_bytes().chunks(2) {
+            let byte = ::std::str::from_utf8(pair).ok().and_then(|pair| u8::from_str_radix(pair, 16).ok());
+            match byte {
+                Some(byte) => self.decoded.push(byte),
+                None => return Err(self.invalid("has something other than hex where the bytes should be"))
+            }
+        }
+
+        Ok(())
+    }
+
+    fn invalid(&self, reason : &str) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, format!("line {} of the hexdump {}", self.line_number, reason))
+    }
+}
+
+impl<R: Read> Read for HexdumpReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.decoded.len() {
+            self.decoded.clear();
+            self.pos = 0;
+
+            let mut line = Vec::new();
+            if self.inner.read_until(b'\n', &mut line)? == 0 {
+                return Ok(0);
+            }
+
+            self.line_number += 1;
+            self.parse_line(&line)?;
+        }
+
+        let n = buf.len().min(self.decoded.len() - self.pos);
+        buf[..n].copy_from_slice(&self.decoded[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use encrypt_reader;
+
+    #[test]
+    fn hexdumps_show_every_column_and_read_back_the_output() {
+        let input = b"Hello world";
+        let key = b"key";
+
+        let mut dump = Vec::new();
+        assert_eq!(hexdump_reader(&mut Cursor::new(&input[..]), key, &mut dump).unwrap(), 11);
+
+        let dump = String::from_utf8(dump).unwrap();
+        let lines : Vec<&str> = dump.lines().collect();
+        assert_eq!(lines[0], HEXDUMP_HEADER);
+        assert_eq!(lines[1], "00000000:  48 65 6c 6c 6f 20 77 6f  6b 65 79 6b 65 79 6b 65  23 00 15 07 0a 59 1c 0a  |#....Y..|");
+        assert_eq!(lines[2], "00000008:  72 6c 64                 79 6b 65                 0b 07 01                 |...|");
+
+        let mut encrypted = Vec::new();
+        encrypt_reader(&mut Cursor::new(&input[..]), key, &mut encrypted).unwrap();
+
+        let mut read_back = Vec::new();
+        HexdumpReader::new(Cursor::new(dump)).read_to_end(&mut read_back).unwrap();
+        assert_eq!(read_back, encrypted);
+    }
+
+    #[test]
+    fn xxd_dumps_are_read_back() {
+        let dump = "00000000: 4865 6c6c 6f20 776f 726c 640a 6361 6665  Hello world.cafe\n\
+                    00000010: 6361 6665                                cafe\n\n";
+        let mut bytes = Vec::new();
+        HexdumpReader::new(Cursor::new(dump)).read_to_end(&mut bytes).unwrap();
+        assert_eq!(bytes, b"Hello world\ncafecafe");
+
+        let err = HexdumpReader::new(Cursor::new("00000000: 486\n")).read_to_end(&mut bytes).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,561 @@
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use rsfs::*;
+use tree::{Mode, Parts, SymlinkFn, to_hex_string, from_hex_string};
+use name_codec::NameCodec;
+use atomic_file::{rewrite_file_then, replace_symlink_then, is_temp_file};
+
+/// The name of the journal file kept at the root of a tree while it's being processed.
+pub const JOURNAL_FILE_NAME : &str = ".xor-journal";
+
+const JOURNAL_VERSION : &str = "xor-journal 1";
+
+/// The type of file a journal is written to for a given file system.
+pub type JournalFile<T> = <<T as GenFS>::OpenOptions as OpenOptions>::File;
+
+/// A single change made to a tree.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Step {
+    /// The contents of "path" were replaced by the XOR'd contents of the temporary file "temp".
+    Content { path : PathBuf, temp : PathBuf },
+    /// The entry "from" was renamed to "to".
+    Rename { from : PathBuf, to : PathBuf },
+    /// The symlink "path" was replaced by the temporary symlink "temp", whose target is rewritten
+    /// from "original".
+    Target { path : PathBuf, temp : PathBuf, original : PathBuf }
+}
+
+/// Records every change made to a tree so an interrupted run can be finished or rolled back.
+///
+/// Each change is recorded, and synced to disk, just before it's made, then recorded again as done
+/// or failed once it's over. A change with neither record was interrupted, and whether it
+/// happened is worked out when the journal is read back: a content change happened if its
+/// temporary file is gone, a rename happened if its source is gone. Temporary files the journal
+/// refers to are never removed until their change is recorded as failed, so a missing one can
+/// only have been renamed into place. Paths are stored relative to the root of the tree.
+pub struct Journal<F> {
+    file : F,
+    root : PathBuf
+}
+
+impl<F: File> Journal<F> {
+
+    /// Records that the contents of "path" are about to be replaced by the temporary file "temp".
+    pub fn record_content(&mut self, path : &Path, temp : &Path) -> io::Result<()> {
+        let line = format!("content {} {}", self.encode(path), self.encode(temp));
+        self.append(&line)
+    }
+
+    /// Records that "from" is about to be renamed to "to".
+    pub fn record_rename(&mut self, from : &Path, to : &Path) -> io::Result<()> {
+        let line = format!("rename {} {}", self.encode(from), self.encode(to));
+        self.append(&line)
+    }
+
+    /// Records that the change, or undo, most recently recorded was made.
+    pub fn record_done(&mut self) -> io::Result<()> {
+        self.append("done")
+    }
+
+    /// Records that the change, or undo, most recently recorded failed and so didn't happen.
+    pub fn record_failed(&mut self) -> io::Result<()> {
+        self.append("failed")
+    }
+
+    /// Records that the latest change, or undo, of the step at "index" was interrupted before it
+    /// was made, so its temporary file can be removed.
+    fn record_abandoned(&mut self, index : usize) -> io::Result<()> {
+        self.append(&format!("failed {}", index))
+    }
+
+    /// Records that the symlink "path", which points at "original", is about to be replaced by the
+    /// temporary symlink "temp".
+    pub fn record_target(&mut self, path : &Path, temp : &Path, original : &Path) -> io::Result<()> {
+        let line = format!("target {} {} {}", self.encode(path), self.encode(temp), to_hex_string(&path_to_bytes(original)));
+        self.append(&line)
+    }
+
+    /// Records that the step at "index" is about to be undone. Undoing a content or target change
+    /// goes through the temporary file "temp".
+    fn record_undo(&mut self, index : usize, temp : Option<&Path>) -> io::Result<()> {
+        let line = match temp {
+            Some(temp) => format!("undo {} {}", index, self.encode(temp)),
+            None => format!("undo {}", index)
+        };
+        self.append(&line)
+    }
+
+    fn encode(&self, path : &Path) -> String {
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+        to_hex_string(&path_to_bytes(relative))
+    }
+
+    fn append(&mut self, line : &str) -> io::Result<()> {
+        self.file.write_all(line.as_bytes())?;
+        self.file.write_all(b"\n")?;
+        self.file.flush()?;
+        self.file.sync_data()
+    }
+}
+
+/// Starts a new journal at the root of a tree, recording the mode, name encoding and parts of the
+/// run. Fails with `io::ErrorKind::AlreadyExists` if an interrupted run already left a journal
+/// there.
+pub fn create_journal<T: GenFS>(fs: &T, root : &Path, mode : Mode, codec : NameCodec, parts : Parts) -> io::Result<Journal<JournalFile<T>>> {
+    let file = fs.new_openopts()
+        .write(true)
+        .create_new(true)
+        .open(journal_path(root))?;
+
+    let mut journal = Journal { file, root : root.to_path_buf() };
+    let mode_name = match mode {
+        Mode::Encrypt => "encrypt",
+        Mode::Decrypt => "decrypt"
+    };
+    journal.append(&format!("{} {} {} {}", JOURNAL_VERSION, mode_name, codec, parts))?;
+
+    Ok(journal)
+}
+
+/// Opens the existing journal at the root of a tree so more changes can be recorded.
+pub fn reopen_journal<T: GenFS>(fs: &T, root : &Path) -> io::Result<Journal<JournalFile<T>>> {
+    let file = fs.new_openopts()
+        .append(true)
+        .open(journal_path(root))?;
+
+    Ok(Journal { file, root : root.to_path_buf() })
+}
+
+/// Deletes the journal once every change has been made or undone.
+pub fn remove_journal<T: GenFS>(fs: &T, root : &Path) -> io::Result<()> {
+    fs.remove_file(journal_path(root))
+}
+
+/// The path of the journal for the tree at "root".
+pub fn journal_path(root : &Path) -> PathBuf {
+    root.join(JOURNAL_FILE_NAME)
+}
+
+/// Returns true if the path names a journal file.
+pub fn is_journal_file(path : &Path) -> bool {
+    path.file_name().map(|name| name == JOURNAL_FILE_NAME).unwrap_or(false)
+}
+
+/// A recorded step along with whether it was carried out and whether it has since been undone.
+/// The outcomes are whether the step, and its undo, were recorded as done or as failed.
+#[derive(Debug)]
+struct RecordedStep {
+    step : Step,
+    outcome : Option<bool>,
+    undo_temp : Option<Option<PathBuf>>,
+    undo_outcome : Option<bool>,
+    done : bool,
+    undone : bool
+}
+
+/// What an interrupted run got through, as read back from its journal.
+#[derive(Debug)]
+pub struct JournalState {
+    pub mode : Mode,
+    pub name_codec : NameCodec,
+    pub parts : Parts,
+    root : PathBuf,
+    steps : Vec<RecordedStep>,
+    done_contents : HashSet<PathBuf>,
+    done_renames : HashSet<PathBuf>
+}
+
+impl JournalState {
+
+    /// Returns true if the contents of the file at "path" have already been XOR'd, or for a
+    /// symlink, if its target has already been rewritten.
+    pub fn content_done(&self, path : &Path) -> bool {
+        self.done_contents.contains(path.strip_prefix(&self.root).unwrap_or(path))
+    }
+
+    /// Returns true if the entry at "path" got there by being renamed, so it has been fully
+    /// processed.
+    pub fn renamed_to(&self, path : &Path) -> bool {
+        self.done_renames.contains(path.strip_prefix(&self.root).unwrap_or(path))
+    }
+
+    /// Returns true if a rollback of this journal has been started.
+    pub fn rollback_started(&self) -> bool {
+        self.steps.iter().any(|s| s.undo_temp.is_some())
+    }
+
+    /// The steps that were carried out and not yet undone, most recent first, along with their
+    /// index in the journal.
+    fn steps_to_undo(&self) -> Vec<(usize, &Step)> {
+        self.steps.iter()
+            .enumerate()
+            .rev()
+            .filter(|&(_, s)| s.done && !s.undone)
+            .map(|(i, s)| (i, &s.step))
+            .collect()
+    }
+}
+
+/// Reads the journal left at the root of a tree by an interrupted run, if there is one.
+pub fn read_journal<T: GenFS>(fs: &T, root : &Path) -> io::Result<Option<JournalState>> {
+    let mut contents = String::new();
+    match fs.open_file(journal_path(root)) {
+        Ok(mut file) => { file.read_to_string(&mut contents)?; },
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e)
+    }
+
+    // The header is the version, the mode and, for journals written since other encodings were
+    // added, the name encoding, then for journals written since parts could be chosen, the parts.
+    let mut lines = contents.lines();
+    let header = lines.next().unwrap_or("");
+    if !header.starts_with(JOURNAL_VERSION) {
+        return Err(invalid_journal("unrecognised header"));
+    }
+
+    let fields : Vec<&str> = header[JOURNAL_VERSION.len()..].split_whitespace().collect();
+    let mode = match fields.first() {
+        Some(&"encrypt") => Mode::Encrypt,
+        Some(&"decrypt") => Mode::Decrypt,
+        _ => return Err(invalid_journal("unrecognised mode"))
+    };
+    let name_codec = match fields.get(1) {
+        Some(codec) => codec.parse().map_err(|_| invalid_journal("unrecognised name encoding"))?,
+        None => NameCodec::Hex
+    };
+    let parts = match fields.get(2) {
+        Some(parts) => parts.parse().map_err(|_| invalid_journal("unrecognised parts"))?,
+        None => Parts::all()
+    };
+
+    let mut steps : Vec<RecordedStep> = Vec::new();
+
+    // The step most recently recorded, and whether that record was an undo, so a following
+    // "done" or "failed" record can be applied to it. An outcome is only applied to a record
+    // that doesn't have one yet, a failure before anything was recorded doesn't undo the step
+    // before it.
+    let mut last : Option<(usize, bool)> = None;
+
+    for line in lines {
+        let fields : Vec<&str> = line.split(' ').collect();
+        match (fields[0], fields.len()) {
+            ("content", 3) => {
+                steps.push(RecordedStep::new(Step::Content { path : decode(fields[1])?, temp : decode(fields[2])? }));
+                last = Some((steps.len() - 1, false));
+            },
+            ("target", 4) => {
+                steps.push(RecordedStep::new(Step::Target { path : decode(fields[1])?, temp : decode(fields[2])?, original : decode(fields[3])? }));
+                last = Some((steps.len() - 1, false));
+            },
+            ("rename", 3) => {
+                steps.push(RecordedStep::new(Step::Rename { from : decode(fields[1])?, to : decode(fields[2])? }));
+                last = Some((steps.len() - 1, false));
+            },
+            ("undo", 2) | ("undo", 3) => {
+                let index : usize = fields[1].parse().map_err(|_| invalid_journal("bad step index"))?;
+                let temp = match fields.get(2) {
+                    Some(temp) => Some(decode(temp)?),
+                    None => None
+                };
+                let step = steps.get_mut(index).ok_or_else(|| invalid_journal("unknown step index"))?;
+                step.undo_temp = Some(temp);
+                step.undo_outcome = None;
+                last = Some((index, true));
+            },
+            ("done", 1) | ("failed", 1) => {
+                let outcome = match last {
+                    Some((index, true)) => &mut steps[index].undo_outcome,
+                    Some((index, false)) => &mut steps[index].outcome,
+                    None => return Err(invalid_journal("outcome recorded before any step"))
+                };
+                if outcome.is_none() {
+                    *outcome = Some(fields[0] == "done");
+                }
+            },
+            ("failed", 2) => {
+                let index : usize = fields[1].parse().map_err(|_| invalid_journal("bad step index"))?;
+                let step = steps.get_mut(index).ok_or_else(|| invalid_journal("unknown step index"))?;
+                if step.undo_temp.is_some() {
+                    step.undo_outcome = Some(false);
+                } else {
+                    step.outcome = Some(false);
+                }
+            },
+            ("", 1) => (),
+            _ => return Err(invalid_journal("unrecognised record"))
+        }
+    }
+
+    let exists = |path : &Path| fs.symlink_metadata(root.join(path)).is_ok();
+    let mut done_contents = HashSet::new();
+    let mut done_renames = HashSet::new();
+
+    for recorded in steps.iter_mut() {
+        recorded.done = recorded.outcome.unwrap_or_else(|| match recorded.step {
+            Step::Content { ref temp, .. } | Step::Target { ref temp, .. } => !exists(temp),
+            Step::Rename { ref from, .. } => !exists(from)
+        });
+
+        recorded.undone = match (&recorded.undo_temp, &recorded.step) {
+            (&None, _) => false,
+            _ if recorded.undo_outcome.is_some() => recorded.undo_outcome == Some(true),
+            (&Some(Some(ref temp)), _) => !exists(temp),
+            (&Some(None), Step::Rename { to, .. }) => !exists(to),
+            _ => false
+        };
+
+        if recorded.done && !recorded.undone {
+            match recorded.step {
+                Step::Content { ref path, .. } | Step::Target { ref path, .. } => { done_contents.insert(path.clone()); },
+                Step::Rename { ref to, .. } => { done_renames.insert(to.clone()); }
+            }
+        }
+    }
+
+    Ok(Some(JournalState { mode, name_codec, parts, root : root.to_path_buf(), steps, done_contents, done_renames }))
+}
+
+impl RecordedStep {
+    fn new(step : Step) -> RecordedStep {
+        RecordedStep {
+            step,
+            outcome : None,
+            undo_temp : None,
+            undo_outcome : None,
+            done : false,
+            undone : false
+        }
+    }
+}
+
+/// Undoes every change recorded in the journal at the root of a tree, most recent first, then
+/// removes the journal. An interrupted rollback can be continued by calling this again.
+/// Restoring rewritten symlink targets needs "create_symlink", see `Walker::creating_symlinks_with`.
+pub fn roll_back<T: GenFS>(fs: &T, root : &Path, key : &[u8], create_symlink : Option<SymlinkFn<T>>) -> io::Result<()> {
+    let state = match read_journal(fs, root)? {
+        Some(state) => state,
+        None => return Err(io::Error::new(io::ErrorKind::NotFound, "there is no journal to roll back"))
+    };
+    let mut journal = reopen_journal(fs, root)?;
+    remove_leftover_temps(fs, root, &state, &mut journal)?;
+
+    for (index, step) in state.steps_to_undo() {
+        let result = match *step {
+            Step::Content { ref path, .. } => {
+                debug!("Undoing the XOR of {:?}", path);
+                rewrite_file_then(fs, &root.join(path), key, |temp| journal.record_undo(index, Some(temp)))
+            },
+            Step::Target { ref path, ref original, .. } => {
+                debug!("Restoring the target of {:?} to {:?}", path, original);
+                match create_symlink {
+                    Some(create_symlink) => replace_symlink_then(fs, create_symlink, original, &root.join(path), |temp| journal.record_undo(index, Some(temp))),
+                    None => Err(io::Error::other(format!("restoring the target of the symlink {:?} needs a file system that can create symlinks", path)))
+                }
+            },
+            Step::Rename { ref from, ref to } => {
+                debug!("Undoing the rename of {:?} to {:?}", from, to);
+                let (from, to) = (root.join(from), root.join(to));
+
+                if fs.symlink_metadata(&from).is_ok() {
+                    Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{:?} already exists", from)))
+                } else {
+                    journal.record_undo(index, None).and_then(|_| fs.rename(&to, &from))
+                }
+            }
+        };
+
+        if let Err(e) = result {
+            let _ = journal.record_failed();
+            return Err(e);
+        }
+        let _ = journal.record_done();
+    }
+
+    remove_journal(fs, root)
+}
+
+/// Removes the temporary files an interrupted run left in the tree at "root", before the run is
+/// resumed or rolled back.
+///
+/// A temporary file the journal refers to is only still there if its change was never made, so
+/// the change is recorded as failed before the file is removed. Otherwise the missing file would
+/// be taken to mean the change was made. Temporary files the journal doesn't refer to were never
+/// recorded, and the originals they were made from are untouched.
+pub fn remove_leftover_temps<T: GenFS, F: File>(fs: &T, root : &Path, state : &JournalState, journal : &mut Journal<F>) -> io::Result<()> {
+    let exists = |path : &Path| fs.symlink_metadata(path).is_ok();
+    let mut referenced = HashSet::new();
+
+    for (index, recorded) in state.steps.iter().enumerate() {
+        let step_temp = match recorded.step {
+            Step::Content { ref temp, .. } | Step::Target { ref temp, .. } => Some((temp, recorded.outcome)),
+            Step::Rename { .. } => None
+        };
+        let undo_temp = match recorded.undo_temp {
+            Some(Some(ref temp)) => Some((temp, recorded.undo_outcome)),
+            _ => None
+        };
+
+        for (temp, outcome) in step_temp.into_iter().chain(undo_temp) {
+            let temp = root.join(temp);
+            if outcome != Some(true) && exists(&temp) {
+                if outcome.is_none() {
+                    journal.record_abandoned(index)?;
+                }
+                info!("Removing leftover temporary file {:?}", temp);
+                fs.remove_file(&temp)?;
+            }
+            referenced.insert(temp);
+        }
+    }
+
+    // Directories are listed with a stack rather than by recursion, as in `Walker::walk`.
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let entries = match fs.read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                debug!("Not looking for temporary files in {:?} because: {}", dir, e);
+                continue;
+            }
+        };
+
+        for entry in entries.filter_map(|e| e.ok()) {
+            let path = entry.path();
+            if entry.file_type().map(|t| t.is_dir()).unwrap_or(false) {
+                dirs.push(path);
+            } else if is_temp_file(&path) && !referenced.contains(&path) {
+                info!("Removing leftover temporary file {:?}", path);
+                fs.remove_file(&path)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn decode(field : &str) -> io::Result<PathBuf> {
+    let bytes = from_hex_string(field).map_err(|_| invalid_journal("bad path"))?;
+    Ok(path_from_bytes(bytes))
+}
+
+fn invalid_journal(details : &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("the journal is corrupt: {}", details))
+}
+
+#[cfg(unix)]
+fn path_to_bytes(path : &Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes : Vec<u8>) -> PathBuf {
+    use std::os::unix::ffi::OsStringExt;
+    PathBuf::from(OsString::from_vec(bytes))
+}
+
+#[cfg(not(unix))]
+fn path_to_bytes(path : &Path) -> Vec<u8> {
+    path.to_string_lossy().into_owned().into_bytes()
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes : Vec<u8>) -> PathBuf {
+    PathBuf::from(OsString::from(String::from_utf8_lossy(&bytes).into_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rsfs::mem::FS;
+    use std::io::Write;
+    use tree::{Walker, encrypt_path, resume_path, rollback_path};
+
+    fn read_file_contents<T: GenFS, P: AsRef<Path>>(fs: &T, path: P) -> Vec<u8> {
+        let mut data : Vec<u8> = Vec::new();
+        fs.open_file(path).unwrap().read_to_end(&mut data).unwrap();
+        data
+    }
+
+    /// Builds /root/{a, b, d/c} then simulates a run with the key "G" that was killed after
+    /// finishing "a" and XORing the contents of "b" but before renaming it.
+    fn interrupted_tree() -> FS {
+        let fs = FS::new();
+        fs.create_dir_all("/root/d").unwrap();
+        fs.create_file("/root/a").unwrap().write_all(b"aaa").unwrap();
+        fs.create_file("/root/b").unwrap().write_all(b"bbb").unwrap();
+        fs.create_file("/root/d/c").unwrap().write_all(b"ccc").unwrap();
+
+        let root = Path::new("/root");
+        let key = [71_u8];
+
+        let journal = create_journal(&fs, root, Mode::Encrypt, NameCodec::Hex, Parts::all()).unwrap();
+        Walker::new(&fs, &key, Mode::Encrypt).with_journal(journal).xor_file(&root.join("a"));
+
+        let mut journal = reopen_journal(&fs, root).unwrap();
+        let b = root.join("b");
+        rewrite_file_then(&fs, &b, &key, |temp| journal.record_content(&b, temp)).unwrap();
+
+        fs
+    }
+
+    #[test]
+    fn resume_finishes_without_xoring_twice() {
+        let fs = interrupted_tree();
+
+        resume_path(&fs, Path::new("/root"), &[71]).unwrap();
+
+        assert_eq!(read_file_contents(&fs, "/root/26"), b"&&&");
+        assert_eq!(read_file_contents(&fs, "/root/25"), b"%%%");
+        assert_eq!(read_file_contents(&fs, "/root/23/24"), b"$$$");
+        assert!(fs.metadata(journal_path(Path::new("/root"))).is_err());
+    }
+
+    #[test]
+    fn rollback_restores_the_original_tree() {
+        let fs = interrupted_tree();
+
+        rollback_path(&fs, Path::new("/root"), &[71]).unwrap();
+
+        assert_eq!(read_file_contents(&fs, "/root/a"), b"aaa");
+        assert_eq!(read_file_contents(&fs, "/root/b"), b"bbb");
+        assert_eq!(read_file_contents(&fs, "/root/d/c"), b"ccc");
+        assert!(fs.metadata(journal_path(Path::new("/root"))).is_err());
+    }
+
+    #[test]
+    fn temporary_files_of_unfinished_changes_are_never_taken_as_finished() {
+        let fs = FS::new();
+        let root = Path::new("/root");
+        fs.create_dir_all(root).unwrap();
+        fs.create_file("/root/b").unwrap().write_all(b"bbb").unwrap();
+
+        // Killed once the XOR'd contents of "b" were recorded but before they replaced it, and
+        // while another file was being copied to a temporary file that was never recorded.
+        let mut journal = create_journal(&fs, root, Mode::Encrypt, NameCodec::Hex, Parts::all()).unwrap();
+        fs.create_file("/root/.xor-tmp.1.0").unwrap().write_all(b"%%%").unwrap();
+        journal.record_content(&root.join("b"), &root.join(".xor-tmp.1.0")).unwrap();
+        fs.create_file("/root/.xor-tmp.1.1").unwrap().write_all(b"part").unwrap();
+
+        let state = read_journal(&fs, root).unwrap().unwrap();
+        remove_leftover_temps(&fs, root, &state, &mut reopen_journal(&fs, root).unwrap()).unwrap();
+        assert!(fs.metadata("/root/.xor-tmp.1.0").is_err());
+        assert!(fs.metadata("/root/.xor-tmp.1.1").is_err());
+        assert!(!read_journal(&fs, root).unwrap().unwrap().content_done(&root.join("b")));
+
+        resume_path(&fs, root, &[71]).unwrap();
+        assert_eq!(read_file_contents(&fs, "/root/25"), b"%%%");
+    }
+
+    #[test]
+    fn encrypt_path_refuses_to_run_over_a_journal() {
+        let fs = interrupted_tree();
+
+        let err = encrypt_path(&fs, Path::new("/root"), &[71], &Mode::Encrypt).unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(read_file_contents(&fs, "/root/b"), b"%%%");
+    }
+}
@@ -0,0 +1,242 @@
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use rsfs::{GenFS, Metadata};
+use hex::FromHex;
+use base64;
+
+/// Where the key bytes are read from.
+#[derive(Debug, PartialEq, Eq)]
+pub enum KeySource {
+    /// The raw contents of a file.
+    File(PathBuf),
+    /// The UTF-8 bytes of the given text.
+    Text(String),
+    /// A hex encoded key, whitespace is ignored.
+    Hex(String),
+    /// A base64 encoded key, whitespace is ignored.
+    Base64(String),
+    /// The value of an environment variable.
+    Env(String),
+    /// Everything that can be read from an already open file descriptor.
+    Fd(i32),
+    /// The deprecated behaviour of the "key" option: a file if one exists at the given path,
+    /// otherwise the text itself.
+    Guess(String)
+}
+
+/// The reasons a key couldn't be resolved.
+#[derive(Debug)]
+pub enum KeyError {
+    Io(String, io::Error),
+    InvalidHex(String),
+    InvalidBase64(String),
+    MissingEnv(String),
+    NotUnicodeEnv(String),
+    Empty(String)
+}
+
+impl fmt::Display for KeyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            KeyError::Io(ref source, ref err) => write!(f, "failed to read the key from {} because: {}", source, err),
+            KeyError::InvalidHex(ref details) => write!(f, "the key isn't valid hex: {}", details),
+            KeyError::InvalidBase64(ref details) => write!(f, "the key isn't valid base64: {}", details),
+            KeyError::MissingEnv(ref name) => write!(f, "the environment variable \"{}\" isn't set", name),
+            KeyError::NotUnicodeEnv(ref name) => write!(f, "the environment variable \"{}\" isn't valid unicode", name),
+            KeyError::Empty(ref source) => write!(f, "the key read from {} is empty, an empty key would leave the data unencrypted", source)
+        }
+    }
+}
+
+impl Error for KeyError {
+    fn description(&self) -> &str {
+        "failed to resolve the key"
+    }
+
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            KeyError::Io(_, ref err) => Some(err),
+            _ => None
+        }
+    }
+}
+
+impl KeySource {
+
+    /// Reads the key bytes from this source.
+    /// Files are read through the given file system, a key with no bytes is an error.
+    pub fn read_key<T: GenFS>(&self, fs: &T) -> Result<Vec<u8>, KeyError> {
+        let key_bytes = match *self {
+            KeySource::File(ref path) => read_key_file(fs, path)?,
+            KeySource::Text(ref text) => text.clone().into_bytes(),
+            KeySource::Hex(ref text) => {
+                let compact = strip_whitespace(text);
+                Vec::<u8>::from_hex(&compact).map_err(|e| KeyError::InvalidHex(e.to_string()))?
+            },
+            KeySource::Base64(ref text) => {
+                let compact = strip_whitespace(text);
+                base64::decode(&compact).map_err(|e| KeyError::InvalidBase64(e.to_string()))?
+            },
+            KeySource::Env(ref name) => match env::var(name) {
+                Ok(value) => value.into_bytes(),
+                Err(env::VarError::NotPresent) => return Err(KeyError::MissingEnv(name.clone())),
+                Err(env::VarError::NotUnicode(_)) => return Err(KeyError::NotUnicodeEnv(name.clone()))
+            },
+            KeySource::Fd(fd) => read_key_fd(fd)?,
+            KeySource::Guess(ref value) => {
+                let path = Path::new(value);
+                if fs.metadata(path).map(|m| m.is_file()).unwrap_or(false) {
+                    eprintln!("WARNING: \"{}\" is being read as a key file. The \"key\" option is deprecated, use \"key-file\" or \"key-string\" to say which is meant.", value);
+                    read_key_file(fs, path)?
+                } else {
+                    if value.contains('/') || value.contains(::std::path::MAIN_SEPARATOR) {
+                        eprintln!("WARNING: \"{}\" looks like a path but no such file exists, so the text itself is being used as the key. The \"key\" option is deprecated, use \"key-file\" or \"key-string\" to say which is meant.", value);
+                    }
+                    value.clone().into_bytes()
+                }
+            }
+        };
+
+        if key_bytes.is_empty() {
+            return Err(KeyError::Empty(self.describe()));
+        }
+
+        Ok(key_bytes)
+    }
+
+    /// A short description of the source for use in messages.
+    fn describe(&self) -> String {
+        match *self {
+            KeySource::File(ref path) => format!("the file {:?}", path),
+            KeySource::Text(_) => String::from("the key string"),
+            KeySource::Hex(_) => String::from("the hex key"),
+            KeySource::Base64(_) => String::from("the base64 key"),
+            KeySource::Env(ref name) => format!("the environment variable \"{}\"", name),
+            KeySource::Fd(fd) => format!("file descriptor {}", fd),
+            KeySource::Guess(ref value) => format!("\"{}\"", value)
+        }
+    }
+}
+
+fn strip_whitespace(text : &str) -> String {
+    text.chars().filter(|c| !c.is_whitespace()).collect()
+}
+
+fn read_key_file<T: GenFS>(fs: &T, path : &Path) -> Result<Vec<u8>, KeyError> {
+    let mut key_bytes = Vec::new();
+
+    fs.open_file(path)
+        .and_then(|mut file| file.read_to_end(&mut key_bytes))
+        .map_err(|e| KeyError::Io(format!("the file {:?}", path), e))?;
+
+    Ok(key_bytes)
+}
+
+/// Reads the key from a descriptor the caller opened. Stdin, stdout and stderr are refused since
+/// they carry the data being XOR'd, and the descriptor is left open for the caller to close.
+#[cfg(unix)]
+fn read_key_fd(fd : i32) -> Result<Vec<u8>, KeyError> {
+    use std::fs::File;
+    use std::mem::ManuallyDrop;
+    use std::os::unix::io::FromRawFd;
+
+    let source = || format!("file descriptor {}", fd);
+
+    if (0..=2).contains(&fd) {
+        let err = io::Error::new(io::ErrorKind::InvalidInput, "stdin, stdout and stderr can't be used for the key");
+        return Err(KeyError::Io(source(), err));
+    }
+
+    // Only a descriptor that's open can be used as a file, anything else is undefined behaviour.
+    if unsafe { fcntl(fd, F_GETFD) } == -1 {
+        return Err(KeyError::Io(source(), io::Error::last_os_error()));
+    }
+
+    let mut key_bytes = Vec::new();
+
+    // Borrowed rather than owned, so it isn't closed when it's dropped.
+    let mut file = ManuallyDrop::new(unsafe { File::from_raw_fd(fd) });
+    file.read_to_end(&mut key_bytes)
+        .map_err(|e| KeyError::Io(source(), e))?;
+
+    Ok(key_bytes)
+}
+
+#[cfg(unix)]
+const F_GETFD : i32 = 1;
+
+#[cfg(unix)]
+extern "C" {
+    fn fcntl(fd : i32, cmd : i32, ...) -> i32;
+}
+
+#[cfg(not(unix))]
+fn read_key_fd(fd : i32) -> Result<Vec<u8>, KeyError> {
+    let err = io::Error::new(io::ErrorKind::Other, "file descriptors are only supported on unix");
+    Err(KeyError::Io(format!("file descriptor {}", fd), err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rsfs::mem::FS;
+    use std::io::Write;
+
+    #[test]
+    fn encoded_keys_are_decoded() {
+        let fs = FS::new();
+
+        assert_eq!(KeySource::Hex(String::from("68 65\n6c6C6f")).read_key(&fs).unwrap(), b"hello");
+        assert_eq!(KeySource::Base64(String::from("aGVs\nbG8=")).read_key(&fs).unwrap(), b"hello");
+        assert!(KeySource::Hex(String::from("6")).read_key(&fs).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn file_descriptors_are_checked_and_left_open() {
+        use std::fs::File;
+        use std::os::unix::io::AsRawFd;
+
+        let fs = FS::new();
+        for fd in &[-1, 0, 1, 2, 100_000] {
+            match KeySource::Fd(*fd).read_key(&fs) {
+                Err(KeyError::Io(..)) => (),
+                result => panic!("file descriptor {} gave {:?}", fd, result)
+            }
+        }
+
+        // Nothing can be read from /dev/null, which is an empty key rather than a closed descriptor.
+        let null = File::open("/dev/null").unwrap();
+        for _ in 0..2 {
+            match KeySource::Fd(null.as_raw_fd()).read_key(&fs) {
+                Err(KeyError::Empty(..)) => (),
+                result => panic!("/dev/null gave {:?}", result)
+            }
+        }
+    }
+
+    #[test]
+    fn guess_prefers_an_existing_file() {
+        let fs = FS::new();
+        fs.create_file("/key.bin").unwrap().write_all(&[1, 2, 3]).unwrap();
+
+        assert_eq!(KeySource::Guess(String::from("/key.bin")).read_key(&fs).unwrap(), vec![1, 2, 3]);
+        assert_eq!(KeySource::Guess(String::from("secret")).read_key(&fs).unwrap(), b"secret");
+    }
+
+    #[test]
+    fn empty_keys_are_rejected() {
+        let fs = FS::new();
+        fs.create_file("/empty").unwrap();
+
+        match KeySource::File(PathBuf::from("/empty")).read_key(&fs) {
+            Err(KeyError::Empty(_)) => (),
+            other => panic!("expected an empty key error, got {:?}", other)
+        }
+        assert!(KeySource::Text(String::new()).read_key(&fs).is_err());
+        assert!(KeySource::Hex(String::from("  ")).read_key(&fs).is_err());
+    }
+}
@@ -0,0 +1,123 @@
+use std::io;
+
+/// Returns an `InvalidInput` error if the key is empty, which `Keystream::new` panics on.
+pub fn check_key(key : &[u8]) -> io::Result<()> {
+    if key.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "the key must not be empty"));
+    }
+    Ok(())
+}
+
+/// A repeating key that remembers how far into the stream it has been applied.
+///
+/// Each call to `apply` continues from where the previous call stopped, so the output only
+/// depends on the data and never on how the reads happened to split it into chunks.
+pub struct Keystream<'a> {
+    key : &'a [u8],
+    index : usize,
+    offset : u64
+}
+
+impl<'a> Keystream<'a> {
+
+    /// Creates a keystream positioned at the start of the key.
+    ///
+    /// # Panics
+    ///
+    /// If the key is empty, since XORing against nothing would return the plaintext. See
+    /// `check_key` for checking it first.
+    pub fn new(key : &'a [u8]) -> Keystream<'a> {
+        assert!(!key.is_empty(), "the key used for a keystream must not be empty");
+
+        Keystream {
+            key,
+            index : 0,
+            offset : 0
+        }
+    }
+
+    /// Creates a keystream positioned as if "offset" bytes had already been encrypted.
+    ///
+    /// # Panics
+    ///
+    /// If the key is empty, as with `new`.
+    pub fn with_offset(key : &'a [u8], offset : u64) -> Keystream<'a> {
+        let mut keystream = Keystream::new(key);
+        keystream.set_offset(offset);
+        keystream
+    }
+
+    /// The absolute number of bytes the keystream has been applied to so far.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Moves the keystream to an absolute offset, the next byte is XOR'd against the key byte for
+    /// that offset.
+    pub fn set_offset(&mut self, offset : u64) {
+        self.index = (offset % self.key.len() as u64) as usize;
+        self.offset = offset;
+    }
+
+    /// XOR's the bytes in "data" in place, continuing from the current key position.
+    pub fn apply(&mut self, data : &mut [u8]) {
+        for byte in data.iter_mut() {
+            *byte ^= self.key[self.index];
+
+            self.index += 1;
+            if self.index == self.key.len() {
+                self.index = 0;
+            }
+        }
+
+        self.offset += data.len() as u64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn apply_continues_across_calls() {
+        let key = [1_u8, 2_u8, 3_u8];
+        let mut whole = [0_u8; 7];
+        let mut split = [0_u8; 7];
+
+        Keystream::new(&key).apply(&mut whole);
+
+        let mut keystream = Keystream::new(&key);
+        let (first, second) = split.split_at_mut(2);
+        keystream.apply(first);
+        keystream.apply(second);
+
+        assert_eq!(whole, [1, 2, 3, 1, 2, 3, 1]);
+        assert_eq!(split, whole);
+        assert_eq!(keystream.offset(), 7);
+    }
+
+    #[test]
+    fn set_offset_matches_applying_from_the_start() {
+        let key = [1_u8, 2_u8, 3_u8, 4_u8, 5_u8];
+        let mut whole = [0_u8; 12];
+        Keystream::new(&key).apply(&mut whole);
+
+        let mut tail = [0_u8; 5];
+        Keystream::with_offset(&key, 7).apply(&mut tail);
+
+        assert_eq!(tail, whole[7..]);
+    }
+
+    #[test]
+    fn long_keys_are_fully_used() {
+        let key : Vec<u8> = (0..1000).map(|i| (i % 251) as u8 + 1).collect();
+        let mut data = vec![0_u8; 1000];
+
+        let mut keystream = Keystream::new(&key);
+        for chunk in data.chunks_mut(512) {
+            keystream.apply(chunk);
+        }
+
+        assert_eq!(data, key);
+    }
+}
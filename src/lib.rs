@@ -0,0 +1,154 @@
+//! XOR encryption of streams, files and whole directory trees.
+//!
+//! The same key is applied to every byte by repeating it as often as needed. Applying the key a
+//! second time restores the original data, so every function here both encrypts and decrypts.
+//!
+//! Directory trees are processed through the `rsfs::GenFS` trait so the functions work against
+//! the disk or any other file system implementation, such as `rsfs::mem::FS`.
+
+extern crate hex;
+extern crate base64;
+extern crate rsfs;
+
+#[macro_use] extern crate log;
+
+pub mod keystream;
+pub mod adapters;
+pub mod key_source;
+pub mod preflight;
+pub mod tree;
+pub mod journal;
+pub mod marker;
+pub mod links;
+pub mod name_codec;
+pub mod stream_codec;
+pub mod hexdump;
+pub mod manifest;
+pub mod plan;
+pub mod filter;
+pub mod error;
+mod atomic_file;
+
+use std::io::{self, Write, Read};
+
+pub use keystream::Keystream;
+pub use adapters::{XorReader, XorWriter, XorStream};
+pub use key_source::{KeySource, KeyError};
+pub use name_codec::NameCodec;
+pub use stream_codec::{StreamEncoding, EncodingWriter, DecodingReader};
+pub use hexdump::{HexdumpReader, hexdump_reader};
+pub use atomic_file::replace_file_with;
+pub use error::{XorError, Operation};
+pub use tree::{Mode, Parts, Summary, SymlinkPolicy, Walker, encrypt_path, resume_path, rollback_path, xor_entry, xor_file, xor_symlink, xor_dir, rename_entry, to_hex_string, from_hex_string};
+
+/// XOR's the bytes in "data" in place against the key, starting from the beginning of the key.
+///
+/// # Panics
+///
+/// If the key is empty, see `keystream::check_key`.
+pub fn xor_in_place(data : &mut [u8], key : &[u8]) {
+    Keystream::new(key).apply(data);
+}
+
+/// XOR's all the bytes from reader against the provided key then writes the result to the output
+/// writer.
+/// The key position is carried across reads so the output doesn't depend on how reads are split.
+/// Returns the number of bytes written, an empty key is an `InvalidInput` error.
+pub fn encrypt_reader<R, W>(input : &mut R, key : &[u8], output : &mut W) -> io::Result<u64>
+    where R: Read + ?Sized, W: Write + ?Sized {
+
+    keystream::check_key(key)?;
+    let mut keystream = Keystream::new(key);
+    let mut buffer = [0; 512];
+    loop {
+        match input.read(&mut buffer) {
+            Ok(n) => {
+                info!("Read {} bytes", n);
+                if n == 0 {
+                    break;
+                }
+                keystream.apply(&mut buffer[..n]);
+                output.write_all(&buffer[..n])?;
+                output.flush()?;
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e)
+        }
+    }
+    debug!("Encrypted {} bytes", keystream.offset());
+
+    Ok(keystream.offset())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Error};
+
+    #[test]
+    fn xor_in_place_works() {
+        let mut data = *b"hello";
+        xor_in_place(&mut data, &[57]);
+        assert_eq!(&data, b"Q\\UUV");
+    }
+
+    #[test]
+    fn encrypt_reader_works() {
+        let input = "hello";
+        let expected = "Q\\UUV";
+
+        let mut reader = Cursor::new(input.as_bytes());
+        let key_bytes = vec![57;1];
+        let mut writer : Cursor<Vec<u8>> = Cursor::new(Vec::new());
+
+        encrypt_reader(&mut reader, &key_bytes, &mut writer).unwrap();
+
+        let cipher_text = String::from_utf8(writer.into_inner()).unwrap();
+
+        assert_eq!(expected, cipher_text);
+    }
+
+    #[test]
+    fn an_empty_key_is_invalid_input() {
+        let mut writer : Cursor<Vec<u8>> = Cursor::new(Vec::new());
+
+        let err = encrypt_reader(&mut Cursor::new(b"hello"), &[], &mut writer).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(writer.into_inner().is_empty());
+
+        let err = XorStream::new(Cursor::new(Vec::new()), &[]).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    /// Reader that hands out at most one byte per read, like a slow pipe.
+    struct TrickleReader<'a>(&'a [u8]);
+
+    impl<'a> Read for TrickleReader<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+            if self.0.is_empty() || buf.is_empty() {
+                return Ok(0);
+            }
+            buf[0] = self.0[0];
+            self.0 = &self.0[1..];
+            Ok(1)
+        }
+    }
+
+    #[test]
+    fn encrypt_reader_output_does_not_depend_on_read_sizes() {
+        let input : Vec<u8> = (0..2000).map(|i| (i % 256) as u8).collect();
+        let key_bytes : Vec<u8> = (0..700).map(|i| (i % 13) as u8 + 1).collect();
+
+        let mut whole_writer : Cursor<Vec<u8>> = Cursor::new(Vec::new());
+        encrypt_reader(&mut Cursor::new(&input), &key_bytes, &mut whole_writer).unwrap();
+
+        let mut trickle_writer : Cursor<Vec<u8>> = Cursor::new(Vec::new());
+        encrypt_reader(&mut TrickleReader(&input), &key_bytes, &mut trickle_writer).unwrap();
+
+        let whole = whole_writer.into_inner();
+        assert_eq!(whole, trickle_writer.into_inner());
+
+        // Bytes past the first 512 must use the key from offset 512, not from offset 0.
+        assert_eq!(whole[600], input[600] ^ key_bytes[600]);
+    }
+}
@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Identifies a file independently of the paths that link to it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct FileId {
+    pub device : u64,
+    pub inode : u64
+}
+
+/// Looks up the identity of the file at a path, without following symlinks, along with the
+/// number of hard links to it.
+/// `rsfs` has no notion of inodes, so this is supplied by the caller, see `disk_file_id`.
+pub type IdentifyFn = fn(&Path) -> io::Result<(FileId, u64)>;
+
+/// Identifies files on the disk, for use with `rsfs::disk::FS`.
+#[cfg(unix)]
+pub fn disk_file_id(path : &Path) -> io::Result<(FileId, u64)> {
+    use std::fs;
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = fs::symlink_metadata(path)?;
+    Ok((FileId { device : metadata.dev(), inode : metadata.ino() }, metadata.nlink()))
+}
+
+/// The paths in a tree that are hard links to the same file.
+#[derive(Debug)]
+pub struct LinkGroup {
+    /// Every path to the file found in the tree, in the order they were found.
+    pub paths : Vec<PathBuf>,
+    /// The total number of hard links to the file, including any outside the tree.
+    pub link_count : u64
+}
+
+impl LinkGroup {
+
+    /// The number of links to the file from outside the tree.
+    pub fn external_links(&self) -> u64 {
+        self.link_count.saturating_sub(self.paths.len() as u64)
+    }
+}
+
+/// The files in a tree with more than one hard link.
+///
+/// Found before a run starts so the walk can XOR the contents of each file once however many
+/// links it has, and so links from outside the tree can be reported before anything changes.
+/// Only the files the walk selects are looked at, so a link the walk leaves alone, because of the
+/// filter or the depth limits or because it's on another file system, counts as outside the tree.
+#[derive(Debug, Default)]
+pub struct HardLinks {
+    groups : Vec<LinkGroup>,
+    group_of_path : HashMap<PathBuf, usize>
+}
+
+impl HardLinks {
+
+    /// Groups the files among "files" that have more than one hard link. "files" should be the
+    /// files the walk would XOR, see `Walker::selected_files`, with paths given the same way.
+    pub fn find<P: AsRef<Path>>(files : &[P], identify : IdentifyFn) -> io::Result<HardLinks> {
+        let mut groups : Vec<LinkGroup> = Vec::new();
+        let mut group_of_id : HashMap<FileId, usize> = HashMap::new();
+
+        for path in files {
+            let path = path.as_ref();
+            let (id, link_count) = identify(path)?;
+            if link_count < 2 {
+                continue;
+            }
+
+            let index = *group_of_id.entry(id).or_insert_with(|| {
+                groups.push(LinkGroup { paths : Vec::new(), link_count });
+                groups.len() - 1
+            });
+            groups[index].paths.push(path.to_path_buf());
+        }
+
+        let mut group_of_path = HashMap::new();
+        for (index, group) in groups.iter().enumerate() {
+            for path in &group.paths {
+                group_of_path.insert(path.clone(), index);
+            }
+        }
+
+        Ok(HardLinks { groups, group_of_path })
+    }
+
+    /// The groups with links from outside the tree. Contents are replaced rather than changed in
+    /// place, so those links keep the unencrypted contents and stop sharing them with the tree.
+    pub fn externally_linked(&self) -> Vec<&LinkGroup> {
+        self.groups.iter().filter(|group| group.external_links() > 0).collect()
+    }
+
+    /// The other paths in the tree linked to the same file as "path".
+    pub fn other_links(&self, path : &Path) -> Vec<&Path> {
+        match self.group_of_path.get(path) {
+            Some(&index) => self.groups[index].paths.iter()
+                .filter(|other| *other != path)
+                .map(|other| other.as_path())
+                .collect(),
+            None => Vec::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use rsfs::*;
+    use rsfs::mem::FS;
+    use tree::{Mode, Walker};
+
+    /// Takes every file named "a", "b" or "c" to be a link to the same file, with three links.
+    fn linked_by_name(path : &Path) -> io::Result<(FileId, u64)> {
+        match path.file_name().and_then(|name| name.to_str()) {
+            Some("a") | Some("b") | Some("c") => Ok((FileId { device : 1, inode : 1 }, 3)),
+            _ => Ok((FileId { device : 1, inode : 2 }, 1))
+        }
+    }
+
+    fn read_contents(fs : &FS, path : &Path) -> Vec<u8> {
+        let mut contents = Vec::new();
+        fs.open_file(path).unwrap().read_to_end(&mut contents).unwrap();
+        contents
+    }
+
+    #[test]
+    fn hard_linked_contents_are_xored_once() {
+        let fs = FS::new();
+        let root = Path::new("/root");
+        fs.create_dir_all(root.join("sub")).unwrap();
+        fs.create_file(root.join("a")).unwrap().write_all(b"secret").unwrap();
+        fs.hard_link(root.join("a"), root.join("sub/b")).unwrap();
+        fs.hard_link(root.join("a"), root.join("c")).unwrap();
+
+        // "c" is left alone by the walk, so it's a link from outside the tree.
+        let walker = || Walker::new(&fs, &[71], Mode::Encrypt).exclude("c".parse().unwrap());
+        let files = walker().selected_files(root).unwrap();
+        assert!(!files.contains(&root.join("c")));
+
+        let links = HardLinks::find(&files, linked_by_name).unwrap();
+        let external = links.externally_linked();
+        assert_eq!(external.len(), 1);
+        assert_eq!(external[0].paths.len(), 2);
+        assert_eq!(external[0].external_links(), 1);
+
+        walker().with_hard_links(links).run(root).unwrap();
+
+        // "a" is "26" and "sub/b" is "343225/25" after encryption.
+        assert_eq!(read_contents(&fs, &root.join("26")), b"4\"$5\"3");
+        assert_eq!(read_contents(&fs, &root.join("343225/25")), b"4\"$5\"3");
+        assert_eq!(read_contents(&fs, &root.join("c")), b"secret");
+    }
+}
@@ -3,48 +3,55 @@ mod stdout_writer;
 
 extern crate clap;
 extern crate xor_utils;
-extern crate hex;
-extern crate base64;
 extern crate number_prefix;
 extern crate rsfs;
+extern crate xor;
 
 #[macro_use] extern crate log;
 extern crate env_logger;
 
 //use std::fs;
-use std::fmt::Debug;
-use clap::{App, Arg, ArgMatches};
-use std::str::FromStr;
+use clap::{App, Arg, ArgGroup, ArgMatches, ErrorKind};
 use std::io::{self};
 use std::io::{Write, Read};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::process;
 use number_prefix::{binary_prefix, Standalone, Prefixed};
 use rsfs::*;
-use std::ops::DerefMut;
-
-
-/// The mode is used in conjunction with the "recursive" option and determines how file names
-/// will be processed when renaming files.
-/// When in "encrypt" mode, file names are XOR'd then hexified.
-/// When in "decrypt" mode, file names are unhexified then XOR'd.
-#[derive(PartialEq, Eq)]
-enum Mode {
-    Encrypt,
-    Decrypt
-}
+use xor::{KeySource, Mode, NameCodec, Parts, Summary, SymlinkPolicy, Walker, XorError, Operation, StreamEncoding, EncodingWriter, DecodingReader, HexdumpReader, encrypt_reader, hexdump_reader, replace_file_with};
+use xor::journal::{read_journal, journal_path};
+use xor::marker::{is_marked, looks_encrypted};
+use xor::links::HardLinks;
+use xor::plan::PlanFormat;
+use xor::filter::Pattern;
+use xor::preflight::{get_largest_file_size, get_longest_name};
+use stdout_writer::{StdoutWriter, TerminalOutput};
 
 
 static ABOUT: &str = "
 XOR encrypt files or directories using a supplied key.
 
 In it's simplest form, reads input from stdin, encrypts it against a key and writes the result to stdout.
-The \"key\" option can be either a path to a file or a string of characters.
+The key can be read from a file, a string, hex or base64 text, an environment variable or a file descriptor.
 
 When the \"recursive\" option is used, files under a given directory are recursively encrypted.
 Files are renamed by XORing the original name against the provided key, then hexifying the result.
 To decrypt you must use the \"decrypt\" flag, files are then renamed by unhexifying then XORing.
+Other encodings than hex can be chosen with the \"name-encoding\" option.
 ";
 
+/// The run failed, or was refused before anything was changed.
+const EXIT_FAILURE : i32 = 1;
+
+/// The arguments or the key couldn't be used.
+const EXIT_BAD_ARGUMENTS : i32 = 2;
+
+/// A recursive run finished, but some entries failed.
+const EXIT_PARTIAL_FAILURE : i32 = 3;
+
+/// Decrypting turned up signs that the key isn't the one the directory was encrypted with.
+const EXIT_KEY_MISMATCH : i32 = 4;
+
 fn main() {
     env_logger::init().unwrap();
 
@@ -54,13 +61,39 @@ fn main() {
         .about(ABOUT)
         .author("Gavyn Riebau")
         .arg(Arg::with_name("key")
-             .help("The file containing the key data, or a provided string, against which input will be XOR'd.\nThis should be larger than the given input data or will need to be repeated to encode the input data.")
+             .help("Deprecated, use one of the other key options instead.\nThe file containing the key data, or a provided string, against which input will be XOR'd.\nIf a file exists at the given path it's used, otherwise the string itself is the key.")
              .long("key")
              .short("k")
-             .required(true)
              .value_name("KEY"))
+        .arg(Arg::with_name("key-file")
+             .help("The file containing the key data against which input will be XOR'd.\nThis should be larger than the given input data or will need to be repeated to encode the input data.")
+             .long("key-file")
+             .value_name("PATH"))
+        .arg(Arg::with_name("key-string")
+             .help("A string whose bytes are used as the key.")
+             .long("key-string")
+             .value_name("TEXT"))
+        .arg(Arg::with_name("key-hex")
+             .help("The key as hex encoded bytes, whitespace is ignored.")
+             .long("key-hex")
+             .value_name("HEX"))
+        .arg(Arg::with_name("key-base64")
+             .help("The key as base64 encoded bytes, whitespace is ignored.")
+             .long("key-base64")
+             .value_name("BASE64"))
+        .arg(Arg::with_name("key-env")
+             .help("The name of an environment variable whose value is used as the key.")
+             .long("key-env")
+             .value_name("VAR"))
+        .arg(Arg::with_name("key-fd")
+             .help("An open file descriptor from which the key is read until end of file.\nIt's left open, and can't be stdin, stdout or stderr.")
+             .long("key-fd")
+             .value_name("N"))
+        .group(ArgGroup::with_name("key-source")
+             .args(&["key", "key-file", "key-string", "key-hex", "key-base64", "key-env", "key-fd"])
+             .required(true))
         .arg(Arg::with_name("force")
-             .help("Don't show warning prompt if the key size is too small and key bytes will have to be re-used.\nRe-using key bytes makes the encryption vulnerable to being decrypted.")
+             .help("Don't show warning prompt if the key size is too small and key bytes will have to be re-used.\nRe-using key bytes makes the encryption vulnerable to being decrypted.\nAlso allows decrypting a directory that isn't marked as encrypted, and skips the warning for directories that look encrypted.\nAlso allows \"in-place\" to replace a file that has other hard links.")
              .long("force")
              .short("f"))
         .arg(Arg::with_name("decrypt")
@@ -81,12 +114,142 @@ fn main() {
              .conflicts_with("input")
              .conflicts_with("output"))
         .arg(Arg::with_name("output")
-             .help("The file to which encoded data will be written, if omitted output will be written to stdout.\nIt's recommended to write output to a file for cases where the encoded data contains non-unicode characters which would otherwise not be printed to the console.")
+             .help("The file to which encoded data will be written, if omitted output will be written to stdout.\nWhen stdout is a terminal the output is rendered according to the \"terminal-output\" option, otherwise the raw bytes are written.")
              .long("output")
              .short("o")
              .required(false)
              .value_name("FILE"))
-        .get_matches();
+        .arg(Arg::with_name("terminal-output")
+             .help("How output written to stdout is shown when stdout is a terminal.\n\"escape\" shows control characters and bytes outside of ascii the way \"cat -v\" does.\n\"hex\" shows every byte as two hex digits.\n\"refuse\" writes nothing and exits with an error.\n\"raw\" writes the bytes as they are.")
+             .long("terminal-output")
+             .value_name("RENDERING")
+             .possible_values(&["escape", "hex", "refuse", "raw"])
+             .default_value("escape"))
+        .arg(Arg::with_name("input-encoding")
+             .help("How the input is encoded, when not using the \"recursive\" option.\nWhitespace in encoded input is ignored.")
+             .long("input-encoding")
+             .value_name("ENCODING")
+             .possible_values(&["raw", "hex", "base64", "base64url", "base32", "ascii85"])
+             .default_value("raw"))
+        .arg(Arg::with_name("output-encoding")
+             .help("How the output is encoded, when not using the \"recursive\" option.")
+             .long("output-encoding")
+             .value_name("ENCODING")
+             .possible_values(&["raw", "hex", "base64", "base64url", "base32", "ascii85"])
+             .default_value("raw"))
+        .arg(Arg::with_name("wrap")
+             .help("Break encoded output into lines of N characters, by default it's written on one line.")
+             .long("wrap")
+             .value_name("N")
+             .validator(|n| n.parse::<usize>().map(|_| ()).map_err(|_| format!("\"{}\" isn't a line width", n))))
+        .arg(Arg::with_name("hexdump")
+             .help("Write a hexdump rather than the output, when not using the \"recursive\" option.\nEach line shows the offset, the input bytes, the key bytes applied to them, the output bytes and the output as ascii.")
+             .long("hexdump"))
+        .arg(Arg::with_name("in-place")
+             .help("Replace the input file with the output rather than writing it elsewhere.\nThe output is written to a temporary file that's renamed over the input once it's complete, so the input is never left half written.")
+             .long("in-place")
+             .requires("input")
+             .conflicts_with_all(&["output", "recursive"]))
+        .arg(Arg::with_name("backup")
+             .help("Keep a copy of the input file, named by adding SUFFIX to its name, when using the \"in-place\" option.")
+             .long("backup")
+             .value_name("SUFFIX")
+             .requires("in-place"))
+        .arg(Arg::with_name("hexdump-input")
+             .help("Read the input as a hexdump, when not using the \"recursive\" option.\nBoth xxd dumps and dumps written by \"hexdump\" are read, for the latter it's the output bytes that are read, so they can be edited and XOR'd back.")
+             .long("hexdump-input"))
+        .arg(Arg::with_name("name-encoding")
+             .help("How encrypted names are encoded when using the \"recursive\" option.\n\"hex\" and \"lower-hex\" double the length of names.\n\"base32\" is shorter and safe on case-insensitive file systems.\n\"base64url\" is the shortest, but names can clash on case-insensitive file systems.\nThe encoding is recorded in the directory, so decrypting a marked directory always uses the encoding it was encrypted with.")
+             .long("name-encoding")
+             .value_name("ENCODING")
+             .possible_values(&["hex", "lower-hex", "base32", "base64url"])
+             .default_value("hex"))
+        .arg(Arg::with_name("symlinks")
+             .help("What to do with symlinks when using the \"recursive\" option.\n\"skip\" leaves them as they are.\n\"rename\" renames them but leaves their targets as they are.\n\"rewrite\" renames them and also encrypts / decrypts the names in relative targets so they still resolve.\n\"follow\" encrypts / decrypts whatever they point at, then renames them.")
+             .long("symlinks")
+             .value_name("POLICY")
+             .possible_values(&["skip", "rename", "rewrite", "follow"])
+             .default_value("rename"))
+        .arg(Arg::with_name("keep-contents")
+             .help("Leave the contents of files as they are when using the \"recursive\" option, only names are encrypted / decrypted.")
+             .long("keep-contents")
+             .requires("recursive"))
+        .arg(Arg::with_name("keep-file-names")
+             .help("Leave the names of files and symlinks as they are when using the \"recursive\" option.")
+             .long("keep-file-names")
+             .requires("recursive"))
+        .arg(Arg::with_name("keep-dir-names")
+             .help("Leave the names of directories as they are when using the \"recursive\" option.\nThe parts left as they are are recorded in the directory, so decrypting a marked directory always decrypts the parts that were encrypted.")
+             .long("keep-dir-names")
+             .requires("recursive"))
+        .arg(Arg::with_name("quarantine")
+             .help("When decrypting, rename entries whose decrypted names aren't safe to use, such as names containing \"/\" or \"..\", to \"quarantined-\" followed by their encrypted name.\nWithout this they're taken as a sign of the wrong key and fail the run.")
+             .long("quarantine")
+             .requires("decrypt"))
+        .arg(Arg::with_name("include")
+             .help("Only process the entries matching this glob when using the \"recursive\" option, along with everything in the directories that match. Can be given more than once.\nGlobs are gitignore style: a glob without a \"/\" matches names at any depth, otherwise it matches paths from the directory.\nWhen decrypting, globs are matched against the decrypted names.")
+             .long("include")
+             .value_name("GLOB")
+             .multiple(true)
+             .number_of_values(1)
+             .validator(|glob| glob.parse::<Pattern>().map(|_| ()))
+             .requires("recursive"))
+        .arg(Arg::with_name("exclude")
+             .help("Leave the entries matching this glob alone when using the \"recursive\" option, along with everything in the directories that match. Can be given more than once, and wins over \"include\".\nA \".xorignore\" file in any directory does the same for the entries below it, one gitignore style glob per line.")
+             .long("exclude")
+             .value_name("GLOB")
+             .multiple(true)
+             .number_of_values(1)
+             .validator(|glob| glob.parse::<Pattern>().map(|_| ()))
+             .requires("recursive"))
+        .arg(Arg::with_name("min-depth")
+             .help("Leave alone the entries fewer than N directories below the directory given to \"recursive\", its own entries being at depth 1.\nDirectories above that depth keep their names, but the entries in them are still processed.")
+             .long("min-depth")
+             .value_name("N")
+             .validator(|n| n.parse::<usize>().map(|_| ()).map_err(|_| format!("\"{}\" isn't a depth", n)))
+             .requires("recursive"))
+        .arg(Arg::with_name("max-depth")
+             .help("Don't process entries more than N directories below the directory given to \"recursive\", its own entries being at depth 1.\nDirectories at the maximum depth are still renamed.")
+             .long("max-depth")
+             .value_name("N")
+             .validator(|n| n.parse::<usize>().map(|_| ()).map_err(|_| format!("\"{}\" isn't a depth", n)))
+             .requires("recursive"))
+        .arg(Arg::with_name("one-file-system")
+             .help("Leave alone directories on a different file system to the directory given to \"recursive\", such as mount points, and don't follow symlinks onto one.")
+             .long("one-file-system")
+             .requires("recursive"))
+        .arg(Arg::with_name("dry-run")
+             .help("Print everything a recursive run would do without changing anything: the files whose contents would be XOR'd, every rename, and the entries that would be skipped or fail.")
+             .long("dry-run")
+             .requires("recursive")
+             .conflicts_with_all(&["resume", "rollback"]))
+        .arg(Arg::with_name("plan-format")
+             .help("How the \"dry-run\" plan is printed.")
+             .long("plan-format")
+             .value_name("FORMAT")
+             .possible_values(&["text", "json"])
+             .default_value("text"))
+        .arg(Arg::with_name("resume")
+             .help("Finish a recursive run that was interrupted, skipping the changes recorded in its journal.\nThe run continues in the mode it was started in.")
+             .long("resume")
+             .requires("recursive")
+             .conflicts_with("rollback"))
+        .arg(Arg::with_name("rollback")
+             .help("Undo the changes made by a recursive run that was interrupted, using its journal.")
+             .long("rollback")
+             .requires("recursive"))
+        .arg(Arg::with_name("keep-going")
+             .help("Carry on past entries that fail during a recursive run rather than stopping at the first one.\nThe failures are listed once the run is finished.")
+             .long("keep-going")
+             .requires("recursive"))
+        .get_matches_safe()
+        .unwrap_or_else(|e| match e.kind {
+            ErrorKind::HelpDisplayed | ErrorKind::VersionDisplayed => e.exit(),
+            _ => {
+                eprintln!("{}", e.message);
+                process::exit(EXIT_BAD_ARGUMENTS);
+            }
+        });
 
 
     // Open handle to the file system.
@@ -100,7 +263,7 @@ fn main() {
     };
 
     // Read all the key bytes into memory.
-    let key_bytes = get_key_bytes(&matches);
+    let key_bytes = get_key_bytes(&fs, &matches);
 
     if matches.is_present("recursive") {
         trace!("Recursively encrypting files and folders.");
@@ -108,324 +271,449 @@ fn main() {
         let starting_dir_name = matches.value_of("recursive").unwrap();
         let starting_dir = Path::new(starting_dir_name);
 
-        if mode == Mode::Decrypt || matches.is_present("force") || check_sizes(&fs, starting_dir, &key_bytes) {
-            encrypt_path(&fs, starting_dir, &key_bytes, &mode);
+        let symlinks : SymlinkPolicy = matches.value_of("symlinks").unwrap().parse().unwrap();
+        let name_codec : NameCodec = matches.value_of("name-encoding").unwrap().parse().unwrap();
+        let parts = Parts {
+            contents : !matches.is_present("keep-contents"),
+            file_names : !matches.is_present("keep-file-names"),
+            dir_names : !matches.is_present("keep-dir-names")
+        };
+        let force = matches.is_present("force");
+
+        // Hard links are found with a walk of their own, so the walker is built the same way for both.
+        let new_walker = || {
+            let mut walker = with_symlinks(Walker::new(&fs, &key_bytes, mode)
+                .name_codec(name_codec)
+                .parts(parts)
+                .symlinks(symlinks)
+                .quarantine(matches.is_present("quarantine"))
+                .keep_going(matches.is_present("keep-going"))
+                .force(force));
+
+            if let Some(depth) = matches.value_of("min-depth") {
+                walker = walker.min_depth(depth.parse().unwrap());
+            }
+            if let Some(depth) = matches.value_of("max-depth") {
+                walker = walker.max_depth(depth.parse().unwrap());
+            }
+            if matches.is_present("one-file-system") {
+                walker = on_one_file_system(walker);
+            }
+            for glob in matches.values_of("include").into_iter().flatten() {
+                walker = walker.include(glob.parse().unwrap());
+            }
+            for glob in matches.values_of("exclude").into_iter().flatten() {
+                walker = walker.exclude(glob.parse().unwrap());
+            }
+            walker
+        };
+
+        let result = match read_journal(&fs, starting_dir) {
+            Ok(Some(_)) if matches.is_present("resume") => new_walker().resume(starting_dir).map(Some),
+            Ok(Some(_)) if matches.is_present("rollback") => new_walker().roll_back(starting_dir).map(|_| None),
+            Ok(Some(_)) => {
+                eprintln!("ERROR: {:?} holds a journal from an interrupted run.\nRunning again would XOR the finished files a second time, use \"--resume\" to finish the run or \"--rollback\" to undo it.", journal_path(starting_dir));
+                process::exit(EXIT_FAILURE);
+            },
+            Ok(None) if matches.is_present("resume") || matches.is_present("rollback") => {
+                eprintln!("ERROR: there is no interrupted run to resume or roll back in {:?}", starting_dir);
+                process::exit(EXIT_FAILURE);
+            },
+            Ok(None) if matches.is_present("dry-run") => {
+                let format : PlanFormat = matches.value_of("plan-format").unwrap().parse().unwrap();
+
+                new_walker()
+                    .with_hard_links(find_hard_links(new_walker(), starting_dir))
+                    .plan(starting_dir)
+                    .map(|plan| {
+                        if let Err(e) = plan.write(&mut io::stdout(), format) {
+                            eprintln!("ERROR: failed to print the plan because: {}", e);
+                            process::exit(EXIT_FAILURE);
+                        }
+                        None
+                    })
+            },
+            Ok(None) => {
+                if mode == Mode::Decrypt || force || (check_encrypted(&fs, starting_dir, name_codec) && check_sizes(&fs, starting_dir, &key_bytes)) {
+                    let hard_links = find_hard_links(new_walker(), starting_dir);
+
+                    if check_hard_links(&hard_links, force) {
+                        new_walker()
+                            .with_hard_links(hard_links)
+                            .run(starting_dir)
+                            .map(Some)
+                    } else {
+                        Ok(None)
+                    }
+                } else {
+                    Ok(None)
+                }
+            },
+            Err(e) => Err(XorError::io(Operation::ReadJournal, starting_dir, e))
+        };
+
+        match result {
+            Ok(Some(summary)) => report_summary(&summary),
+            Ok(None) => (),
+            Err(e) => {
+                eprintln!("ERROR: {}", e);
+                if e.is_key_mismatch() {
+                    process::exit(EXIT_KEY_MISMATCH);
+                }
+                if mode == Mode::Decrypt && e.kind() == io::ErrorKind::InvalidInput {
+                    eprintln!("Use the \"force\" flag to decrypt it anyway.");
+                }
+                process::exit(EXIT_FAILURE);
+            }
         }
     } else {
+        let options = stream_options(&matches);
+
+        if matches.is_present("in-place") {
+            let input = Path::new(matches.value_of("input").unwrap());
+
+            // A symlink is followed so the file it points at is replaced rather than the link.
+            let path = match fs.canonicalize(input) {
+                Ok(path) => path,
+                Err(err) => exit_with(XorError::io(Operation::Encrypt, input, err))
+            };
+            let path = path.as_path();
+
+            let other_links = link_count(path).saturating_sub(1);
+            if other_links > 0 && !matches.is_present("force") {
+                eprintln!("ERROR: {:?} has {} other hard link(s).\nThe file is replaced rather than changed in place, so they would keep the original contents. Use the \"force\" flag to replace it anyway.", input, other_links);
+                process::exit(EXIT_BAD_ARGUMENTS);
+            }
+
+            let backup = matches.value_of("backup").map(|suffix| {
+                let mut backup = path.as_os_str().to_os_string();
+                backup.push(suffix);
+                PathBuf::from(backup)
+            });
+
+            trace!("Replacing the input file with the output.");
+            let result = replace_file_with(&fs, path, backup.as_deref(), |original, temp| {
+                xor_stream(&options, &key_bytes, original, temp)
+            });
+            if let Err(err) = result {
+                exit_with(XorError::io(Operation::Encrypt, path, err));
+            }
+            return;
+        }
 
-        let mut output : Box<Write> = if matches.is_present("output") {
+        if let Some(out_file_name) = matches.value_of("output") {
+            // Without "input" it's whatever stdin was redirected from, which /dev/stdin resolves to.
+            let in_file_name = matches.value_of("input").unwrap_or("/dev/stdin");
+            if is_same_file(&fs, Path::new(in_file_name), Path::new(out_file_name)) {
+                eprintln!("ERROR: the input and output are the same file, {:?}.\nWriting the output would destroy the input before it's read, use \"in-place\" to replace a file with its encrypted contents.", out_file_name);
+                process::exit(EXIT_BAD_ARGUMENTS);
+            }
+        }
+
+        let mut output : Box<dyn Write> = if let Some(out_file_name) = matches.value_of("output") {
             trace!("Writting output to a file.");
 
-            Box::new(fs.new_openopts()
-                     .write(true)
-                     .create(true)
-                     .truncate(true)
-                     .open(matches.value_of("output").unwrap())
-                     .unwrap())
+            let file = fs.new_openopts()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(out_file_name)
+                .unwrap_or_else(|e| exit_with(XorError::io(Operation::Create, Path::new(out_file_name), e)));
+            Box::new(file)
 
         } else {
             trace!("Writting output to stdout.");
-            Box::new(stdout_writer::StdoutWriter{})
+
+            let terminal_output : TerminalOutput = matches.value_of("terminal-output").unwrap().parse().unwrap();
+            match StdoutWriter::new(terminal_output) {
+                Ok(writer) => Box::new(writer),
+                Err(e) => {
+                    eprintln!("ERROR: {}", e);
+                    process::exit(EXIT_FAILURE);
+                }
+            }
         };
 
-        let mut in_reader : Box<Read> = if matches.is_present("input") {
+        let in_reader : Box<dyn Read> = if let Some(in_file_name) = matches.value_of("input") {
             trace!("Reading input from a file.");
-            let in_file_name = matches.value_of("input").unwrap();
-            Box::new(fs.open_file(in_file_name).unwrap())
+            let file = fs.open_file(in_file_name)
+                .unwrap_or_else(|e| exit_with(XorError::io(Operation::Open, Path::new(in_file_name), e)));
+            Box::new(file)
         } else {
             trace!("Reading input from stdin.");
             Box::new(io::stdin())
         };
 
-        encrypt_reader(&mut in_reader, &key_bytes, output.deref_mut());
-    }
-}
-
-/// XOR's all the bytes from reader against the provided key then writes the result to the output
-/// writer.
-fn encrypt_reader(input : &mut Read, key : &Vec<u8>, output : &mut Write) {
-    let mut buffer = [0; 512];
-    loop {
-        match input.read(&mut buffer) {
-            Ok(n) => {
-                info!("Read {} bytes", n);
-                if n == 0 {
-                    break;
-                }
-                let key_repeated = repeat_key(key, n);
-                let encoded_bytes : Vec<u8> = buffer.iter().zip(key_repeated).map(|(d, k)| d ^ k).collect();
-                let _ = output.write_all(encoded_bytes.as_slice());
-                output.flush().unwrap();
+        match xor_stream(&options, &key_bytes, in_reader, &mut output) {
+            // The reader of a pipe went away, such as "head", so the rest isn't wanted.
+            Err(ref err) if err.kind() == io::ErrorKind::BrokenPipe => (),
+            Err(err) => {
+                let input = matches.value_of("input").unwrap_or("-");
+                exit_with(XorError::io(Operation::Encrypt, Path::new(input), err));
             },
-            Err(e) => {
-                error!("Failed to read because: {}", e);
-                break;
-            }
+            Ok(_) => ()
         }
     }
 }
 
-fn encrypt_path<T: GenFS>(fs: &T, p : &Path, key : &Vec<u8>, mode : &Mode) {
-    for item in fs.read_dir(p).unwrap() {
-        match item {
-            Ok(entry) => xor_entry(fs, &entry, key, mode),
-            Err(err) => info!("Failed to read entry because: {}", err)
-        }
-    }
-}
-
-fn xor_entry<T: rsfs::DirEntry, U: GenFS>(fs: &U, entry : &T, key : &Vec<u8>, mode : &Mode) {
-    match entry.file_type() {
-        Ok(entry_type) => {
-            if entry_type.is_dir() {
-                xor_dir(fs, &entry.path(), key, mode);
-            } else if entry_type.is_file() {
-                xor_file(fs, &entry.path(), key, mode);
-            } else if entry_type.is_symlink() {
-                xor_symlink(fs, &entry.path(), key, mode);
-            }
-        },
-        Err(err) => info!("Failed to get filetype for DirEntry {:?} because: {}", entry, err)
-    }
+/// How stream mode reads its input and writes its output.
+struct StreamOptions {
+    input_encoding : StreamEncoding,
+    output_encoding : StreamEncoding,
+    wrap : usize,
+    hexdump : bool,
+    hexdump_input : bool
 }
 
-fn xor_file<T, P>(fs: &T, path : &P, key : &Vec<u8>, mode : &Mode)
-    where T: GenFS, P: AsRef<Path> + Debug {
-
-        debug!("Encrypting file {:?}", path);
+/// Reads the stream mode options, exiting with an error message if they can't be used together.
+fn stream_options<'a>(matches: &'a ArgMatches<'a>) -> StreamOptions {
+    let options = StreamOptions {
+        input_encoding : matches.value_of("input-encoding").unwrap().parse().unwrap(),
+        output_encoding : matches.value_of("output-encoding").unwrap().parse().unwrap(),
+        wrap : matches.value_of("wrap").map(|n| n.parse().unwrap()).unwrap_or(0),
+        hexdump : matches.is_present("hexdump"),
+        hexdump_input : matches.is_present("hexdump-input")
+    };
 
-        let mut in_file = fs.open_file(path).unwrap();
-        let mut file_bytes : Vec<u8> = Vec::new();
+    if options.wrap > 0 && options.output_encoding == StreamEncoding::Raw {
+        eprintln!("ERROR: \"wrap\" needs an \"output-encoding\" other than raw");
+        process::exit(EXIT_BAD_ARGUMENTS);
+    }
+    if options.hexdump && options.output_encoding != StreamEncoding::Raw {
+        eprintln!("ERROR: \"hexdump\" can't be used with an \"output-encoding\"");
+        process::exit(EXIT_BAD_ARGUMENTS);
+    }
+    if options.hexdump_input && options.input_encoding != StreamEncoding::Raw {
+        eprintln!("ERROR: \"hexdump-input\" can't be used with an \"input-encoding\"");
+        process::exit(EXIT_BAD_ARGUMENTS);
+    }
 
-        //in_file.seek(SeekFrom::Start(0)).unwrap();
+    options
+}
 
-        let num_read = in_file.read_to_end(&mut file_bytes).unwrap();
+/// XOR's everything read from "input" into "output", decoding and encoding them as the options ask.
+fn xor_stream<R : Read + 'static, W : Write>(options : &StreamOptions, key_bytes : &[u8], input : R, output : W) -> io::Result<()> {
+    let mut input : Box<dyn Read> = if options.hexdump_input {
+        Box::new(HexdumpReader::new(input))
+    } else {
+        Box::new(DecodingReader::new(input, options.input_encoding))
+    };
+    let mut output = EncodingWriter::new(output, options.output_encoding).wrap(options.wrap);
 
-        // TODO: REMOVE
-        println!("in_file: {:?}", in_file);
-        println!("path: {}", path.as_ref().to_str().unwrap());
-        println!("num_read: {}", num_read);
-        //println!("file_bytes: {:?}", file_bytes);
+    if options.hexdump {
+        hexdump_reader(&mut input, key_bytes, &mut output)?;
+    } else {
+        encrypt_reader(&mut input, key_bytes, &mut output)?;
+    }
 
-        let key_repeated = repeat_key(key, file_bytes.len() as usize);
+    output.finish()
+}
 
-        let encrypted_bytes : Vec<u8> = file_bytes.iter()
-            .zip(key_repeated)
-            .map(|(d, k)| d ^ k)
-            .collect();
+/// Returns true if "input" and "output" are the same file, whether they're the same path once
+/// resolved or, where it can be told, hard links to the same file.
+fn is_same_file<T: GenFS>(fs: &T, input : &Path, output : &Path) -> bool {
+    // An output that doesn't exist yet can't be the input.
+    match (fs.canonicalize(input), fs.canonicalize(output)) {
+        (Ok(input), Ok(output)) => input == output || same_file_id(&input, &output),
+        _ => false
+    }
+}
 
-        let mut out_file = fs.new_openopts()
-            .write(true)
-            .truncate(true)
-            .open(path)
-            .unwrap();
+#[cfg(unix)]
+fn same_file_id(first : &Path, second : &Path) -> bool {
+    match (xor::links::disk_file_id(first), xor::links::disk_file_id(second)) {
+        (Ok((first, _)), Ok((second, _))) => first == second,
+        _ => false
+    }
+}
 
+#[cfg(not(unix))]
+fn same_file_id(_first : &Path, _second : &Path) -> bool {
+    false
+}
 
-        // TODO: REMOVE
-        println!("Encrypted bytes are: {:?}", encrypted_bytes);
+/// The number of hard links to the file at "path", or 1 where it can't be told.
+#[cfg(unix)]
+fn link_count(path : &Path) -> u64 {
+    xor::links::disk_file_id(path).map(|(_, link_count)| link_count).unwrap_or(1)
+}
 
+#[cfg(not(unix))]
+fn link_count(_path : &Path) -> u64 {
+    1
+}
 
-        out_file.write_all(&encrypted_bytes).unwrap();
+/// Prints what a recursive run did, exiting with `EXIT_KEY_MISMATCH` if anything showed the key
+/// is wrong, or `EXIT_PARTIAL_FAILURE` if anything else failed.
+fn report_summary(summary : &Summary) {
+    eprintln!("{}", summary);
 
-        rename_entry(fs, path, key, mode);
+    if summary.failures.iter().any(|failure| failure.is_key_mismatch()) {
+        eprintln!("The key looks like a different one to the key the directory was encrypted with, use \"--rollback\" to undo the run.");
+        process::exit(EXIT_KEY_MISMATCH);
     }
-
-fn xor_symlink<T, P>(fs: &T, entry : &P, key : &Vec<u8>, mode : &Mode)
-    where T: GenFS, P: AsRef<Path> + Debug {
-        debug!("Encrypting symlink {:?}", entry);
-        rename_entry(fs, entry, key, mode);
+    if !summary.succeeded() {
+        eprintln!("The journal was kept, use \"--resume\" to finish the run once the failures are fixed or \"--rollback\" to undo it.");
+        process::exit(EXIT_PARTIAL_FAILURE);
     }
+}
 
-fn xor_dir<T, P>(fs: &T, path : &P, key : &Vec<u8>, mode : &Mode)
-    where T: GenFS, P: AsRef<Path> + Debug {
-
-        debug!("Encrypting dir {:?}", path);
+/// Prints the error and exits with `EXIT_FAILURE`.
+fn exit_with(error : XorError) -> ! {
+    eprintln!("ERROR: {}", error);
+    process::exit(EXIT_FAILURE);
+}
 
-        for child in fs.read_dir(&path).unwrap() {
-            let child_path = child.unwrap();
-            let metadata = child_path.metadata().unwrap();
+/// Reads the key from whichever key option was given, exiting with an error message if the key
+/// can't be read or is empty.
+fn get_key_bytes<'a, T: GenFS>(fs: &T, matches: &'a ArgMatches<'a>) -> Vec<u8> {
+    let key_source = get_key_source(matches);
 
-            if metadata.is_dir() {
-                xor_dir(fs, &child_path.path(), key, mode);
-            }
-            else if metadata.is_file() {
-                xor_file(fs, &child_path.path(), key, mode);
-            }
-            else if metadata.is_dir() {
-                rename_entry(fs, &child_path.path(), key, mode);
-            }
+    match key_source.read_key(fs) {
+        Ok(key_bytes) => key_bytes,
+        Err(err) => {
+            eprintln!("ERROR: {}", err);
+            process::exit(EXIT_BAD_ARGUMENTS);
         }
-
-        rename_entry(fs, path, key, mode);
     }
+}
 
-/// //Renames a directory entry.
-/// //When "mode" is Mode::Encrypt, the name of the entry is XOR'd then hexlified.
-/// When "mode" is Mode::Decrypt, the name of the entry is unhexlified then XOR'd.
-fn rename_entry<T, P>(fs: &T, path : P, key : &Vec<u8>, mode : &Mode)
-    where T: GenFS, P: AsRef<Path> + Debug {
-
-        if let Some(original_name) = path.as_ref().file_name() {
-            debug!("original_name: {:?}", original_name);
-
-            let key_repeated = repeat_key(key, original_name.len());
-
-            // If in Encrypt mode use the filename as is.
-            // If in Decrypt mode unhexify the filename before getting it's bytes.
-            let input_bytes = match *mode {
-                Mode::Encrypt => String::from_str(original_name.to_str().unwrap()).unwrap().into_bytes(),
-                Mode::Decrypt => from_hex_string(&String::from_str(original_name.to_str().unwrap()).unwrap())
-            };
-
-            // Xor encrypt the name.
-            let mut encrypted = Vec::with_capacity(input_bytes.len());
-            for (d, k) in input_bytes.iter().zip(key_repeated) {
-                encrypted.push(d ^ k);
-            }
-
-            // If in Encrypt mode hexify the filename.
-            // If in Decrypt mode just use the filename as is.
-            let replaced_name = match *mode {
-                Mode::Encrypt => to_hex_string(encrypted),
-                Mode::Decrypt => String::from_utf8(encrypted).unwrap()
-            };
-            debug!("replaced_name: {}", replaced_name);
-
-            let parent_path = path.as_ref().parent().unwrap();
-            let src_file_path = parent_path.join(&original_name);
-            let dst_file_path = parent_path.join(&replaced_name);
-
-            debug!("Moving {:?} to {:?}", src_file_path, dst_file_path);
-
-            match fs.rename(&src_file_path, &dst_file_path) {
-                Ok(_) => trace!("Renamed path '{:?}' to '{:?}'", &src_file_path, &dst_file_path),
-                Err(e) => error!("Failed to rename '{:?}' to '{:?}' because: {}", &src_file_path, &dst_file_path, e)
+fn get_key_source<'a>(matches: &'a ArgMatches<'a>) -> KeySource {
+    if let Some(path) = matches.value_of("key-file") {
+        KeySource::File(PathBuf::from(path))
+    } else if let Some(text) = matches.value_of("key-string") {
+        KeySource::Text(String::from(text))
+    } else if let Some(text) = matches.value_of("key-hex") {
+        KeySource::Hex(String::from(text))
+    } else if let Some(text) = matches.value_of("key-base64") {
+        KeySource::Base64(String::from(text))
+    } else if let Some(name) = matches.value_of("key-env") {
+        KeySource::Env(String::from(name))
+    } else if let Some(fd) = matches.value_of("key-fd") {
+        match fd.parse() {
+            Ok(fd) => KeySource::Fd(fd),
+            Err(_) => {
+                eprintln!("ERROR: \"{}\" isn't a valid file descriptor number", fd);
+                process::exit(EXIT_BAD_ARGUMENTS);
             }
         }
+    } else {
+        KeySource::Guess(String::from(matches.value_of("key").unwrap()))
     }
+}
 
-/// Create a vector of bytes equal in length to the name of the file.
-/// If the key is too small it'll be repeated to make up the required length.
-fn repeat_key(key : &Vec<u8>, required_len : usize) -> Vec<u8> {
-    let mut key_repeated = Vec::with_capacity(required_len);
+fn check_sizes<T: GenFS>(fs: &T, starting_directory : &Path, key_bytes : &[u8]) -> bool {
+    let mut should_continue : bool = true;
 
-    while key_repeated.len() < required_len {
-        for &b in key {
-            key_repeated.push(b);
+    let key_size = key_bytes.len();
+    let largest_file_size = get_largest_file_size(fs, starting_directory);
+    let longest_name = get_longest_name(fs, starting_directory);
 
-            if key_repeated.len() == required_len {
-                break;
-            }
-        }
+    if largest_file_size > key_size as u64 || longest_name > key_size {
+        print_keysize_warning(key_size, largest_file_size, longest_name);
+        let answer = show_prompt();
+        should_continue = answer == 'y';
     }
 
-    key_repeated
+    should_continue
 }
 
-fn to_hex_string(bytes: Vec<u8>) -> String {
-    let strings: Vec<String> = bytes
-        .iter()
-        .map(|b| format!("{:02X}", b))
-        .collect();
-
-    strings.join("")
-}
+/// Warns when an unmarked directory looks like it has already been encrypted, since encrypting it
+/// again would garble the names. Returns false if the user chooses not to continue.
+fn check_encrypted<T: GenFS>(fs: &T, starting_directory : &Path, name_codec : NameCodec) -> bool {
+    let mut should_continue : bool = true;
 
-fn from_hex_string(hex : &String) -> Vec<u8> {
-    hex::FromHex::from_hex(hex).unwrap()
-}
+    if !is_marked(fs, starting_directory).unwrap_or(false) && looks_encrypted(fs, starting_directory, name_codec) {
+        println!("
+================================================================================
+WARNING: The directory looks like it has already been encrypted.
+================================================================================
 
-fn get_key_bytes<'a>(_matches: &'a ArgMatches<'a>) -> Vec<u8> {
-    let key_bytes : Vec<u8> = Vec::new();
+Every file and directory name is valid {}, which is how encrypted names look.
+Encrypting it a second time would XOR the contents back to plaintext and
+encode the names again, leaving the directory garbled.
 
-    // let key = matches.value_of("key").unwrap();
+Use the \"decrypt\" flag if you meant to decrypt it.
 
-    // // If the key is a file, read the contents of the file.
-    // // Otherwise if key is a string, use the string bytes.
-    // if Path::new(key).exists() {
-    //     File::open(key).unwrap().read_to_end(&mut key_bytes).unwrap();
-    // } else {
-    //     key_bytes = key.to_string().into_bytes();
-    // }
+================================================================================", name_codec);
+        let answer = show_prompt();
+        should_continue = answer == 'y';
+    }
 
-    key_bytes
+    should_continue
 }
 
-/// Recursively searches the supplied path and finds the size of the largest file.
-fn get_largest_file_size(path : &Path) -> u64 {
-    let mut size : u64 = 0;
-
-    match path.metadata() {
-        Ok(metadata) => {
-
-            // Check if the current file is the largest.
-            if path.is_file() {
-                size = metadata.len();
-            } else if path.is_dir() {
-                // Check if any of the child files are the largest.
-                match path.read_dir() {
-                    Ok(entries) => {
-                        for entry in entries {
-                            let entry_size = get_largest_file_size(entry.unwrap().path().as_path());
-
-                            if entry_size > size {
-                                size = entry_size;
-                            }
-                        }
-                    },
-                    Err(err) => info!("Failed to read directory {:?} because: {}", path, err)
-                }
-            }
-        },
-        Err(err) => info!("Failed to get metadata for path {:?} because: {}", path, err)
-    }
-
-    size
+/// Allows the walker to create symlinks, which is needed to rewrite their targets.
+#[cfg(unix)]
+fn with_symlinks<'a, T: GenFS + unix_ext::GenFSExt>(walker : Walker<'a, T>) -> Walker<'a, T> {
+    walker.creating_symlinks_with(xor::tree::create_symlink)
 }
 
-/// Recursively searches the supplied path and finds the length of the longest file/directory name.
-fn get_longest_name<T: GenFS>(fs: &T, path : &Path) -> usize {
-    let mut size : usize = 0;
+#[cfg(not(unix))]
+fn with_symlinks<'a, T: GenFS>(walker : Walker<'a, T>) -> Walker<'a, T> {
+    walker
+}
 
-    // Check if the current entry name is the longest.
-    if let Some(name) = path.file_name() {
-        let length = name.len();
+/// Keeps the walker on the file system of the directory it starts in.
+#[cfg(unix)]
+fn on_one_file_system<'a, T: GenFS>(walker : Walker<'a, T>) -> Walker<'a, T> {
+    walker.one_file_system(xor::links::disk_file_id)
+}
 
-        if length > size {
-            size = length;
-        }
+#[cfg(not(unix))]
+fn on_one_file_system<'a, T: GenFS>(_walker : Walker<'a, T>) -> Walker<'a, T> {
+    eprintln!("ERROR: \"one-file-system\" isn't supported on this platform");
+    process::exit(EXIT_BAD_ARGUMENTS);
+}
 
-        if path.is_dir() {
-            // Check if any of the child directory / file names are the longest.
-            for entry_result in fs.read_dir(path).unwrap() {
-                if let Ok(entry) = entry_result {
-                    let entry_size = get_longest_name(fs, entry.path().as_path());
+/// Finds the hard linked files among the files the walker selects, so their contents are only
+/// XOR'd once. If the tree can't be walked at all the run itself reports why.
+#[cfg(unix)]
+fn find_hard_links<T: GenFS>(walker : Walker<T>, starting_directory : &Path) -> HardLinks {
+    let files = match walker.selected_files(starting_directory) {
+        Ok(files) => files,
+        Err(_) => return HardLinks::default()
+    };
 
-                    if entry_size > size {
-                        size = entry_size;
-                    }
-                }
-            }
+    match HardLinks::find(&files, xor::links::disk_file_id) {
+        Ok(hard_links) => hard_links,
+        Err(e) => {
+            eprintln!("ERROR: failed to look for hard links in {:?} because: {}", starting_directory, e);
+            process::exit(EXIT_FAILURE);
         }
     }
+}
 
-    size
+#[cfg(not(unix))]
+fn find_hard_links<T: GenFS>(_walker : Walker<T>, _starting_directory : &Path) -> HardLinks {
+    HardLinks::default()
 }
 
-fn check_sizes<T: GenFS>(fs: &T, starting_directory : &Path, key_bytes : &Vec<u8>) -> bool {
-    let mut should_continue : bool = true;
+/// Reports files that are also hard linked from outside the directory, since those links will
+/// keep the original contents. Returns false if the user chooses not to continue.
+fn check_hard_links(hard_links : &HardLinks, force : bool) -> bool {
+    let externally_linked = hard_links.externally_linked();
 
-    let key_size = key_bytes.len();
-    let largest_file_size = get_largest_file_size(starting_directory);
-    let longest_name = get_longest_name(fs, starting_directory);
+    if externally_linked.is_empty() {
+        return true;
+    }
 
-    if largest_file_size > key_size as u64 || longest_name > key_size {
-        print_keysize_warning(key_size, largest_file_size, longest_name);
-        let answer = show_prompt();
-        should_continue = answer == 'y';
+    println!("
+================================================================================
+WARNING: Some files are hard linked from outside the directory.
+================================================================================
+");
+    for group in &externally_linked {
+        println!("{:?} has {} link(s) outside the directory", group.paths[0], group.external_links());
     }
+    println!("
+The files inside the directory will be replaced by their encrypted contents.
+The links outside the directory will keep the original, unencrypted contents
+and will no longer be linked to the files inside the directory.
 
-    should_continue
+================================================================================");
+
+    force || show_prompt() == 'y'
 }
 
 fn show_prompt() -> char {
@@ -477,177 +765,3 @@ Sizes:");
 
     println!("\n================================================================================");
 }
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use rsfs::mem::{FS, Permissions};
-    use rsfs::unix_ext::PermissionsExt;
-    use std::io::{Cursor, Error};
-
-    fn read_file_contents<T: GenFS, P: AsRef<Path>>(fs: &T, path: P) -> Vec<u8> {
-        let mut x = fs.open_file(path).unwrap();
-        let mut data : Vec<u8> = Vec::new();
-
-        let _num_read = x.read_to_end(&mut data).unwrap();
-
-        data
-    }
-
-    #[test]
-    fn to_hex_string_works() {
-        let input_string = String::from("hello");
-        let input_bytes = input_string.into_bytes();
-
-        let hex_string = to_hex_string(input_bytes);
-        assert_eq!(hex_string, "68656C6C6F");
-    }
-
-    #[test]
-    fn from_hex_string_works() {
-        let input_string = String::from("68656C6C6F");
-        let ascii_bytes = from_hex_string(&input_string);
-        let expected_bytes = vec![104, 101, 108, 108, 111];
-
-        assert_eq!(expected_bytes, ascii_bytes);
-    }
-
-    #[test]
-    fn encrypt_reader_works() {
-        let input = "hello";
-        let expected = "Q\\UUV";
-
-        let mut reader = Cursor::new(input.as_bytes());
-        let key_bytes = vec![57;1];
-        let mut writer : Cursor<Vec<u8>> = Cursor::new(Vec::new());
-
-        encrypt_reader(&mut reader, &key_bytes, &mut writer);
-
-        let cipher_text = String::from_utf8(writer.into_inner()).unwrap();
-
-        assert_eq!(expected, cipher_text);
-    }
-
-    #[test]
-    fn xor_file_encrypt_mode_works() {
-        // Arrange.
-
-        // Setup the input file
-        let fs = FS::new();
-        let input_data = "hello world".as_bytes();
-        let input_path = Path::new("/input.txt");
-        let key = vec![71];
-        let mode = Mode::Encrypt;
-        let mut input_file = fs.create_file(&input_path).unwrap();
-        input_file.write(input_data).unwrap();
-
-        // Act.
-        xor_file(&fs, &input_path, &key, &mode);
-
-        // Assert.
-        let filenames : Vec<String> = fs.read_dir("/")
-            .unwrap()
-            .map(|x| x.unwrap())
-            .map(|x| x.path().into_os_string())
-            .map(|x| x.into_string().unwrap())
-            .collect();
-
-        let mut encrypted_file = fs.open_file("/2E2937323369333F33").unwrap();
-        let mut encrypted_bytes : Vec<u8> = Vec::new();
-        encrypted_file.read_to_end(&mut encrypted_bytes).unwrap();
-
-        // Filename is XOR'd against the key then encoded to hex
-        assert_eq!(filenames, vec!["/2E2937323369333F33"]);
-        assert_eq!(encrypted_bytes, vec![0x2f_u8, 0x22_u8, 0x2b_u8, 0x2b_u8, 0x28_u8, 0x67_u8, 0x30_u8, 0x28_u8, 0x35_u8, 0x2b_u8, 0x23_u8]);
-    }
-
-    #[test]
-    fn xor_file_decrypt_mode_works() {
-        // Arrange.
-
-        // Setup the input file
-        let fs = FS::new();
-        let input_path = Path::new("/2E2937323369333F33");
-        let input_data = [0x2f_u8, 0x22_u8, 0x2b_u8, 0x2b_u8, 0x28_u8, 0x67_u8, 0x30_u8, 0x28_u8, 0x35_u8, 0x2b_u8, 0x23_u8];
-        let mut input_file = fs.create_file(&input_path).unwrap();
-        input_file.write(&input_data).unwrap();
-
-        let key = vec![71];
-        //let mode = Mode::Decrypt;
-
-        // Act.
-        xor_file(&fs, &input_path, &key, &Mode::Decrypt);
-
-        // Assert.
-        let mut output_file = fs.open_file("/input.txt").unwrap();
-        let mut encrypted_bytes : Vec<u8> = Vec::new();
-        output_file.read_to_end(&mut encrypted_bytes).unwrap();
-
-        // Filename is XOR'd against the key then encoded to hex
-        assert_eq!(encrypted_bytes, "hello world".as_bytes());
-    }
-
-    #[test]
-    fn xor_directory_encrypt_mode_works() {
-
-        // Arrange.
-
-        // Use a single letter key 'G'
-        let key = vec![71];
-
-        // Make a filesystem as follows:
-        //
-        // parent_dir
-        //    |
-        //    +-- child_dir
-        //    |     |
-        //    |     +-- file_a
-        //    |     |
-        //    |     +-- file_b
-        //    |
-        //    +-- file_c
-        //
-
-        let fs = FS::new();
-        let file_a_contents_starting : [u8; 5] = [1_u8, 2_u8, 3_u8, 4_u8, 5_u8];
-        let file_b_contents_starting : [u8; 5] = [6_u8, 7_u8, 8_u8, 9_u8, 10_u8];
-        let file_c_contents_starting : [u8; 5] = [11_u8, 12_u8, 13_u8, 14_u8, 15_u8];
-        let file_a_contents_expected : [u8; 5] = [70_u8, 69_u8, 68_u8, 67_u8, 66_u8];
-        let file_b_contents_expected : [u8; 5] = [65_u8, 64_u8, 79_u8, 78_u8, 77_u8];
-        let file_c_contents_expected : [u8; 5] = [76_u8, 75_u8, 74_u8, 73_u8, 72_u8];
-
-        // Create the files
-        fs.new_dirbuilder()
-            .recursive(true)
-            .create("/parent_dir/child_dir")
-            .unwrap();
-
-        let mut file_a = fs.create_file("/parent_dir/child_dir/file_a").unwrap();
-        let mut file_b = fs.create_file("/parent_dir/child_dir/file_b").unwrap();
-        let mut file_c = fs.create_file("/parent_dir/file_c").unwrap();
-
-        // Write contents to files
-        file_a.write_all(&file_a_contents_starting).unwrap();
-        file_b.write_all(&file_b_contents_starting).unwrap();
-        file_c.write_all(&file_c_contents_starting).unwrap();
-
-        xor_dir(&fs, &"parent_dir", &key, &Mode::Encrypt);
-
-        assert!(fs.metadata("/37263522293318232E35").unwrap().is_dir());                                  // parent_dir -> 37263522293318232E35
-        assert!(fs.metadata("/37263522293318232E35/242F2E2B2318232E35").unwrap().is_dir());               // parent_dir/child_dir -> 37263522293318232E35/242F2E2B2318232E35
-        assert!(fs.metadata("/37263522293318232E35/242F2E2B2318232E35/212E2B221826").unwrap().is_file()); // parent_dir/child_dir/file_a -> 37263522293318232E35/242F2E2B2318232E35/212E2B221826
-        assert!(fs.metadata("/37263522293318232E35/242F2E2B2318232E35/212E2B221825").unwrap().is_file()); // parent_dir/child_dir/file_b -> 37263522293318232E35/242F2E2B2318232E35/212E2B221825
-        assert!(fs.metadata("/37263522293318232E35/212E2B221824").unwrap().is_file());                    // parent_dir/file_c -> 37263522293318232E35/212E2B221824
-
-        // Assert contents are XOR'd
-        let file_a_contents_actual : Vec<u8> = read_file_contents(&fs, "/37263522293318232E35/242F2E2B2318232E35/212E2B221826");
-        let file_b_contents_actual : Vec<u8> = read_file_contents(&fs, "/37263522293318232E35/242F2E2B2318232E35/212E2B221825");
-        let file_c_contents_actual : Vec<u8> = read_file_contents(&fs, "/37263522293318232E35/212E2B221824");
-
-        assert_eq!(file_a_contents_actual, file_a_contents_expected);
-        assert_eq!(file_b_contents_actual, file_b_contents_expected);
-        assert_eq!(file_c_contents_actual, file_c_contents_expected);
-    }
-
-}
-
@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use rsfs::*;
+use adapters::{XorReader, XorWriter};
+
+/// The name of the file at the root of an encrypted tree that holds the encrypted names too long
+/// to be used as file names.
+pub const MANIFEST_FILE_NAME : &str = ".xor-names";
+
+/// The first character of a placeholder name. It isn't used by any name encoding, so
+/// placeholders can't be confused with encrypted names.
+pub const PLACEHOLDER_PREFIX : &str = "~";
+
+/// The longest file name, in bytes, most file systems allow.
+pub const DEFAULT_MAX_NAME_LEN : usize = 255;
+
+/// The encrypted names that were replaced by placeholders because they were too long.
+///
+/// The manifest is kept in a single file at the root of the tree, XOR'd against the key as one
+/// stream so the names in it are no easier to read than the names in the tree. Each name is
+/// appended, and synced, before anything is renamed to its placeholder, so the name can always
+/// be restored.
+pub struct NameManifest {
+    root : PathBuf,
+    names : HashMap<String, String>
+}
+
+impl NameManifest {
+
+    /// Reads the manifest of the tree at "root", which is empty if there isn't one.
+    pub fn read<T: GenFS>(fs: &T, root : &Path, key : &[u8]) -> io::Result<NameManifest> {
+        let mut contents = Vec::new();
+        match fs.open_file(manifest_path(root)) {
+            Ok(file) => { XorReader::new(file, key).read_to_end(&mut contents)?; },
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e)
+        }
+
+        let corrupt = || io::Error::new(io::ErrorKind::InvalidData,
+            format!("{:?} is corrupt or was written with a different key", manifest_path(root)));
+        let contents = String::from_utf8(contents).map_err(|_| corrupt())?;
+
+        let mut names = HashMap::new();
+        for line in contents.lines() {
+            let mut fields = line.split(' ');
+            match (fields.next(), fields.next(), fields.next()) {
+                (Some(placeholder), Some(name), None) if placeholder.starts_with(PLACEHOLDER_PREFIX) => {
+                    names.insert(placeholder.to_string(), name.to_string());
+                },
+                _ => return Err(corrupt())
+            }
+        }
+
+        Ok(NameManifest { root : root.to_path_buf(), names })
+    }
+
+    /// Returns the placeholder for an encrypted name, adding the name to the manifest on disk if
+    /// it isn't already there.
+    pub fn add<T: GenFS>(&mut self, fs: &T, key : &[u8], name : &str) -> io::Result<String> {
+        let placeholder = placeholder_for(name);
+
+        if self.names.get(&placeholder).map(|existing| existing == name).unwrap_or(false) {
+            return Ok(placeholder);
+        }
+
+        if self.names.contains_key(&placeholder) {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("the placeholder {} is already used by another name", placeholder)));
+        }
+
+        let file = fs.new_openopts()
+            .create(true)
+            .append(true)
+            .open(manifest_path(&self.root))?;
+
+        // Appended names carry on the keystream from the end of the file.
+        let offset = file.metadata()?.len();
+        let mut writer = XorWriter::with_offset(file, key, offset);
+        writer.write_all(format!("{} {}\n", placeholder, name).as_bytes())?;
+        writer.flush()?;
+        writer.get_ref().sync_data()?;
+
+        self.names.insert(placeholder.clone(), name.to_string());
+        Ok(placeholder)
+    }
+
+    /// The encrypted name a placeholder stands for.
+    pub fn lookup(&self, placeholder : &str) -> Option<&str> {
+        self.names.get(placeholder).map(|name| name.as_str())
+    }
+}
+
+/// A short name that stands in for an encrypted name. It's made from a hash of the encrypted
+/// name, so the same name always gets the same placeholder.
+pub fn placeholder_for(name : &str) -> String {
+    // 64 bit FNV-1a.
+    let mut hash : u64 = 0xcbf2_9ce4_8422_2325;
+    for &byte in name.as_bytes() {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+
+    format!("{}{:016x}", PLACEHOLDER_PREFIX, hash)
+}
+
+/// Returns true if the name is a placeholder for a name in the manifest.
+pub fn is_placeholder(name : &OsStr) -> bool {
+    name.to_str().map(|name| name.starts_with(PLACEHOLDER_PREFIX)).unwrap_or(false)
+}
+
+/// The path of the manifest for the tree at "root".
+pub fn manifest_path(root : &Path) -> PathBuf {
+    root.join(MANIFEST_FILE_NAME)
+}
+
+/// Returns true if the path names a manifest file.
+pub fn is_manifest_file(path : &Path) -> bool {
+    path.file_name().map(|name| name == MANIFEST_FILE_NAME).unwrap_or(false)
+}
+
+/// Removes the manifest once every placeholder in the tree has been restored.
+pub fn remove_manifest<T: GenFS>(fs: &T, root : &Path) -> io::Result<()> {
+    match fs.remove_file(manifest_path(root)) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rsfs::mem::FS;
+
+    #[test]
+    fn names_are_read_back_with_the_same_key() {
+        let fs = FS::new();
+        let root = Path::new("/root");
+        fs.create_dir_all(root).unwrap();
+
+        let mut manifest = NameManifest::read(&fs, root, b"key").unwrap();
+        let first = manifest.add(&fs, b"key", "AABBCC").unwrap();
+        let second = manifest.add(&fs, b"key", "DDEEFF").unwrap();
+        assert_eq!(manifest.add(&fs, b"key", "AABBCC").unwrap(), first);
+
+        let manifest = NameManifest::read(&fs, root, b"key").unwrap();
+        assert_eq!(manifest.lookup(&first), Some("AABBCC"));
+        assert_eq!(manifest.lookup(&second), Some("DDEEFF"));
+
+        let mut contents = String::new();
+        fs.open_file(manifest_path(root)).unwrap().read_to_string(&mut contents).ok();
+        assert!(!contents.contains("AABBCC"));
+    }
+}
@@ -0,0 +1,219 @@
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use rsfs::*;
+use tree::{Mode, Parts};
+use name_codec::NameCodec;
+use manifest::is_placeholder;
+
+/// The name of the file left at the root of an encrypted tree.
+pub const MARKER_FILE_NAME : &str = ".xor-encrypted";
+
+const MARKER_VERSION : &str = "xor-encrypted 1";
+
+const NAME_ENCODING_FIELD : &str = "name-encoding ";
+
+const TRANSFORMED_FIELD : &str = "transformed ";
+
+/// How a marked tree was encrypted, as recorded in its marker.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Marker {
+    /// The encoding of the encrypted names.
+    pub name_codec : NameCodec,
+    /// The parts of the tree that were encrypted.
+    pub parts : Parts
+}
+
+/// The path of the marker for the tree at "root".
+pub fn marker_path(root : &Path) -> PathBuf {
+    root.join(MARKER_FILE_NAME)
+}
+
+/// Returns true if the path names a marker file.
+pub fn is_marker_file(path : &Path) -> bool {
+    path.file_name().map(|name| name == MARKER_FILE_NAME).unwrap_or(false)
+}
+
+/// Returns true if the tree at "root" has been marked as encrypted.
+pub fn is_marked<T: GenFS>(fs: &T, root : &Path) -> io::Result<bool> {
+    read_marker(fs, root).map(|marker| marker.is_some())
+}
+
+/// Reads the marker of the tree at "root", returning how it was encrypted, or None if it isn't
+/// marked. Markers written before other encodings were added mean hex, and markers written before
+/// parts could be chosen mean every part.
+pub fn read_marker<T: GenFS>(fs: &T, root : &Path) -> io::Result<Option<Marker>> {
+    let mut contents = String::new();
+    match fs.open_file(marker_path(root)) {
+        Ok(mut file) => { file.read_to_string(&mut contents)?; },
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e)
+    }
+
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, format!("{:?} isn't a valid marker file", marker_path(root)));
+    let mut lines = contents.lines();
+
+    if lines.next() != Some(MARKER_VERSION) {
+        return Err(invalid());
+    }
+
+    let mut marker = Marker { name_codec : NameCodec::Hex, parts : Parts::all() };
+    for line in lines {
+        if let Some(name_codec) = line.strip_prefix(NAME_ENCODING_FIELD) {
+            marker.name_codec = name_codec.parse().map_err(|_| invalid())?;
+        } else if let Some(parts) = line.strip_prefix(TRANSFORMED_FIELD) {
+            marker.parts = parts.parse().map_err(|_| invalid())?;
+        } else {
+            return Err(invalid());
+        }
+    }
+
+    Ok(Some(marker))
+}
+
+/// Marks the tree at "root" as encrypted, recording the encoding its names were encrypted with
+/// and the parts of it that were encrypted.
+pub fn write_marker<T: GenFS>(fs: &T, root : &Path, codec : NameCodec, parts : Parts) -> io::Result<()> {
+    let mut file = fs.new_openopts()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(marker_path(root))?;
+
+    file.write_all(format!("{}\n{}{}\n{}{}\n", MARKER_VERSION, NAME_ENCODING_FIELD, codec, TRANSFORMED_FIELD, parts).as_bytes())?;
+    file.flush()?;
+    file.sync_all()
+}
+
+/// Removes the marker once the tree at "root" has been decrypted.
+pub fn remove_marker<T: GenFS>(fs: &T, root : &Path) -> io::Result<()> {
+    match fs.remove_file(marker_path(root)) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result
+    }
+}
+
+/// Checks that running in "mode" won't XOR the tree at "root" twice.
+/// A marked tree can't be encrypted again, and an unmarked tree is only decrypted when "force"
+/// is set.
+pub fn check_marker<T: GenFS>(fs: &T, root : &Path, mode : Mode, force : bool) -> io::Result<()> {
+    let marked = is_marked(fs, root)?;
+
+    match mode {
+        Mode::Encrypt if marked => Err(io::Error::new(io::ErrorKind::AlreadyExists,
+            format!("{:?} is marked as already encrypted, encrypting it again would garble it", root))),
+        Mode::Decrypt if !marked && !force => Err(io::Error::new(io::ErrorKind::InvalidInput,
+            format!("{:?} isn't marked as encrypted, decrypting it could garble it", root))),
+        _ => Ok(())
+    }
+}
+
+/// Guesses whether an unmarked tree has already been encrypted with "codec": true if there's at
+/// least one entry below "root" and every name is exactly what "codec" would give for the bytes it
+/// decodes to, or is a placeholder for a name that was too long.
+/// Base64url is never guessed, most short plain names without an extension are valid base64url.
+pub fn looks_encrypted<T: GenFS>(fs: &T, root : &Path, codec : NameCodec) -> bool {
+    if codec == NameCodec::Base64Url {
+        return false;
+    }
+
+    let mut found_entry = false;
+    all_names_decode(fs, root, codec, &mut found_entry) && found_entry
+}
+
+fn all_names_decode<T: GenFS>(fs: &T, dir : &Path, codec : NameCodec, found_entry : &mut bool) -> bool {
+    let entries = match fs.read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return true
+    };
+
+    for entry in entries.filter_map(|e| e.ok()) {
+        let path = entry.path();
+        let name = entry.file_name();
+
+        if name.to_str().map(|n| n.starts_with('.')).unwrap_or(false) {
+            continue;
+        }
+
+        *found_entry = true;
+
+        let decodes = name.to_str()
+            .map(|n| codec.decode(n).map(|bytes| codec.encode(&bytes) == n).unwrap_or(false))
+            .unwrap_or(false);
+        if !decodes && !is_placeholder(&name) {
+            return false;
+        }
+
+        let is_dir = entry.file_type().map(|t| t.is_dir()).unwrap_or(false);
+        if is_dir && !all_names_decode(fs, &path, codec, found_entry) {
+            return false;
+        }
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rsfs::mem::FS;
+    use tree::{encrypt_path, Walker};
+
+    #[test]
+    fn encrypting_marks_the_tree_and_decrypting_unmarks_it() {
+        let fs = FS::new();
+        let root = Path::new("/root");
+        fs.create_dir_all("/root/dir").unwrap();
+        fs.create_file("/root/dir/file").unwrap();
+
+        encrypt_path(&fs, root, &[71], &Mode::Encrypt).unwrap();
+        assert_eq!(read_marker(&fs, root).unwrap(), Some(Marker { name_codec : NameCodec::Hex, parts : Parts::all() }));
+        assert!(looks_encrypted(&fs, root, NameCodec::Hex));
+
+        let err = encrypt_path(&fs, root, &[71], &Mode::Encrypt).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+
+        encrypt_path(&fs, root, &[71], &Mode::Decrypt).unwrap();
+        assert!(!is_marked(&fs, root).unwrap());
+        assert!(fs.metadata("/root/dir/file").unwrap().is_file());
+    }
+
+    #[test]
+    fn trees_look_encrypted_with_the_name_encoding_they_were_encrypted_with() {
+        let fs = FS::new();
+        let root = Path::new("/root");
+        fs.create_dir_all("/root/dir").unwrap();
+        fs.create_file("/root/dir/notes.txt").unwrap();
+        assert!(!looks_encrypted(&fs, root, NameCodec::Base32));
+
+        Walker::new(&fs, &[71], Mode::Encrypt).name_codec(NameCodec::Base32).run(root).unwrap();
+        fs.remove_file(root.join(MARKER_FILE_NAME)).unwrap();
+        assert!(looks_encrypted(&fs, root, NameCodec::Base32));
+    }
+
+    #[test]
+    fn plain_trees_do_not_look_encrypted() {
+        let fs = FS::new();
+        let root = Path::new("/root");
+        for dir in &["/root/src", "/root/docs", "/root/2024", "/root/cafe"] {
+            fs.create_dir_all(dir).unwrap();
+        }
+        for file in &["/root/src/main", "/root/docs/intro", "/root/2024/BEEF", "/root/Makefile", "/root/LICENSE"] {
+            fs.create_file(file).unwrap();
+        }
+
+        for &codec in &[NameCodec::Hex, NameCodec::LowerHex, NameCodec::Base32, NameCodec::Base64Url] {
+            assert!(!looks_encrypted(&fs, root, codec), "{} guessed a plain tree was encrypted", codec);
+        }
+    }
+
+    #[test]
+    fn unmarked_trees_are_only_decrypted_when_forced() {
+        let fs = FS::new();
+        let root = Path::new("/root");
+        fs.create_dir_all("/root").unwrap();
+
+        assert!(check_marker(&fs, root, Mode::Decrypt, false).is_err());
+        assert!(check_marker(&fs, root, Mode::Decrypt, true).is_ok());
+        assert!(check_marker(&fs, root, Mode::Encrypt, false).is_ok());
+    }
+}
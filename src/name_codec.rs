@@ -0,0 +1,176 @@
+use std::fmt;
+use std::str::FromStr;
+use base64;
+use tree::{to_hex_string, from_hex_string};
+
+const BASE32_ALPHABET : &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// How the XOR'd bytes of a name are turned into a file name, and back again.
+///
+/// Every encoding only uses characters that are valid in file names and never starts a name with
+/// ".", so encrypted names can't be confused with the files xor keeps at the root of a tree.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NameCodec {
+    /// Uppercase hex, two characters per byte. This was the only encoding before others were
+    /// added, so it's the default.
+    #[default]
+    Hex,
+    /// Lowercase hex, two characters per byte.
+    LowerHex,
+    /// RFC 4648 base32 without padding, eight characters per five bytes. Only uppercase letters
+    /// are produced so names survive case-insensitive file systems.
+    Base32,
+    /// RFC 4648 base64url without padding, four characters per three bytes. The shortest
+    /// encoding, but names that differ only by case clash on case-insensitive file systems.
+    Base64Url
+}
+
+impl NameCodec {
+
+    /// The name used for the encoding on the command line and in the tree's marker.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            NameCodec::Hex => "hex",
+            NameCodec::LowerHex => "lower-hex",
+            NameCodec::Base32 => "base32",
+            NameCodec::Base64Url => "base64url"
+        }
+    }
+
+    /// Encodes the bytes of a name.
+    pub fn encode(&self, bytes : &[u8]) -> String {
+        match *self {
+            NameCodec::Hex => to_hex_string(bytes),
+            NameCodec::LowerHex => to_hex_string(bytes).to_lowercase(),
+            NameCodec::Base32 => base32_encode(bytes),
+            NameCodec::Base64Url => base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+        }
+    }
+
+    /// Decodes a name produced by `encode`, returning None if it isn't valid for this encoding.
+    /// Hex and base32 accept either case, and base32 also accepts padding.
+    pub fn decode(&self, name : &str) -> Option<Vec<u8>> {
+        match *self {
+            NameCodec::Hex | NameCodec::LowerHex => from_hex_string(name).ok(),
+            NameCodec::Base32 => base32_decode(name),
+            NameCodec::Base64Url => base64::decode_config(name, base64::URL_SAFE_NO_PAD).ok()
+        }
+    }
+
+    /// The length of the name `encode` produces for "len" bytes.
+    pub fn encoded_len(&self, len : usize) -> usize {
+        match *self {
+            NameCodec::Hex | NameCodec::LowerHex => len * 2,
+            NameCodec::Base32 => (len * 8).div_ceil(5),
+            NameCodec::Base64Url => (len * 4).div_ceil(3)
+        }
+    }
+}
+
+impl fmt::Display for NameCodec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for NameCodec {
+    type Err = String;
+
+    fn from_str(s : &str) -> Result<NameCodec, String> {
+        match s {
+            "hex" => Ok(NameCodec::Hex),
+            "lower-hex" => Ok(NameCodec::LowerHex),
+            "base32" => Ok(NameCodec::Base32),
+            "base64url" => Ok(NameCodec::Base64Url),
+            _ => Err(format!("\"{}\" isn't a name encoding, expected hex, lower-hex, base32 or base64url", s))
+        }
+    }
+}
+
+fn base32_encode(bytes : &[u8]) -> String {
+    let mut encoded = String::with_capacity(NameCodec::Base32.encoded_len(bytes.len()));
+    let mut buffer : u16 = 0;
+    let mut bits = 0;
+
+    for &byte in bytes {
+        buffer = (buffer << 8) | u16::from(byte);
+        bits += 8;
+
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[((buffer >> bits) & 0x1F) as usize] as char);
+        }
+    }
+
+    // The last character is padded out with zero bits.
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1F) as usize] as char);
+    }
+
+    encoded
+}
+
+fn base32_decode(name : &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(name.len() * 5 / 8);
+    let mut buffer : u16 = 0;
+    let mut bits = 0;
+
+    for c in name.trim_end_matches('=').bytes() {
+        let value = match c.to_ascii_uppercase() {
+            c @ b'A'..=b'Z' => c - b'A',
+            c @ b'2'..=b'7' => c - b'2' + 26,
+            _ => return None
+        };
+
+        buffer = ((buffer << 5) | u16::from(value)) & 0x0FFF;
+        bits += 5;
+
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+        }
+    }
+
+    // Whatever is left over must be the zero padding of the last character, any more than that
+    // means characters are missing.
+    if bits >= 5 || buffer & ((1 << bits) - 1) != 0 {
+        return None;
+    }
+
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base32_matches_rfc_4648() {
+        let vectors = [("", ""), ("f", "MY"), ("fo", "MZXQ"), ("foo", "MZXW6"), ("foob", "MZXW6YQ"), ("fooba", "MZXW6YTB"), ("foobar", "MZXW6YTBOI")];
+
+        for &(plain, encoded) in &vectors {
+            assert_eq!(NameCodec::Base32.encode(plain.as_bytes()), encoded);
+            assert_eq!(NameCodec::Base32.decode(encoded).unwrap(), plain.as_bytes());
+            assert_eq!(NameCodec::Base32.encoded_len(plain.len()), encoded.len());
+        }
+
+        assert_eq!(NameCodec::Base32.decode("mzxw6ytboi======").unwrap(), b"foobar");
+        assert_eq!(NameCodec::Base32.decode("M"), None);
+        assert_eq!(NameCodec::Base32.decode("MZ"), None);
+    }
+
+    #[test]
+    fn every_codec_round_trips() {
+        let bytes : Vec<u8> = (0..=255).collect();
+
+        for codec in &[NameCodec::Hex, NameCodec::LowerHex, NameCodec::Base32, NameCodec::Base64Url] {
+            for len in 0..8 {
+                let encoded = codec.encode(&bytes[len * 30..len * 30 + len]);
+                assert_eq!(encoded.len(), codec.encoded_len(len));
+                assert!(!encoded.starts_with('.'));
+                assert_eq!(codec.decode(&encoded).unwrap(), &bytes[len * 30..len * 30 + len]);
+                assert_eq!(codec.name().parse::<NameCodec>().unwrap(), *codec);
+            }
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use std::fmt;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// A change a recursive run would make, or an entry it would skip or fail on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// The contents of the file would be XOR'd.
+    Content { path : PathBuf },
+    /// The file would be replaced by a hard link to "existing", whose contents are XOR'd instead.
+    Link { path : PathBuf, existing : PathBuf },
+    /// The entry would be renamed.
+    Rename { from : PathBuf, to : PathBuf },
+    /// The entry would be renamed to a placeholder because its encrypted name is too long.
+    Placeholder { from : PathBuf, to : PathBuf },
+    /// The target of the symlink would be rewritten.
+    Target { path : PathBuf, from : PathBuf, to : PathBuf },
+    /// The entry would be left as it is.
+    Skip { path : PathBuf, reason : String },
+    /// The entry would fail to be processed.
+    Fail { path : PathBuf, reason : String }
+}
+
+/// How a plan is printed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlanFormat {
+    /// One line per action.
+    Text,
+    /// A JSON array with one object per action.
+    Json
+}
+
+impl FromStr for PlanFormat {
+    type Err = String;
+
+    fn from_str(s : &str) -> Result<PlanFormat, String> {
+        match s {
+            "text" => Ok(PlanFormat::Text),
+            "json" => Ok(PlanFormat::Json),
+            _ => Err(format!("\"{}\" isn't a plan format, expected text or json", s))
+        }
+    }
+}
+
+/// Everything a recursive run would do, in the order it would do it, see `Walker::plan`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Plan {
+    pub actions : Vec<Action>
+}
+
+impl Plan {
+
+    /// Creates an empty plan.
+    pub fn new() -> Plan {
+        Plan { actions : Vec::new() }
+    }
+
+    /// Prints the plan in the given format.
+    pub fn write<W: Write + ?Sized>(&self, output : &mut W, format : PlanFormat) -> io::Result<()> {
+        match format {
+            PlanFormat::Text => self.write_text(output),
+            PlanFormat::Json => self.write_json(output)
+        }
+    }
+
+    /// Prints one line per action.
+    pub fn write_text<W: Write + ?Sized>(&self, output : &mut W) -> io::Result<()> {
+        for action in &self.actions {
+            writeln!(output, "{}", action)?;
+        }
+        Ok(())
+    }
+
+    /// Prints a JSON array with one object per action. Paths that aren't valid unicode are printed
+    /// lossily.
+    pub fn write_json<W: Write + ?Sized>(&self, output : &mut W) -> io::Result<()> {
+        writeln!(output, "[")?;
+
+        for (index, action) in self.actions.iter().enumerate() {
+            let fields : Vec<(&str, String)> = match *action {
+                Action::Content { ref path } => vec![("path", path_string(path))],
+                Action::Link { ref path, ref existing } => vec![("path", path_string(path)), ("existing", path_string(existing))],
+                Action::Rename { ref from, ref to } | Action::Placeholder { ref from, ref to } => vec![("from", path_string(from)), ("to", path_string(to))],
+                Action::Target { ref path, ref from, ref to } => vec![("path", path_string(path)), ("from", path_string(from)), ("to", path_string(to))],
+                Action::Skip { ref path, ref reason } | Action::Fail { ref path, ref reason } => vec![("path", path_string(path)), ("reason", reason.clone())]
+            };
+
+            write!(output, "  {{\"action\": \"{}\"", action.name())?;
+            for (name, value) in fields {
+                write!(output, ", \"{}\": \"{}\"", name, json_escape(&value))?;
+            }
+
+            let separator = if index + 1 < self.actions.len() { "," } else { "" };
+            writeln!(output, "}}{}", separator)?;
+        }
+
+        writeln!(output, "]")
+    }
+}
+
+impl Action {
+
+    /// A one word description of the action.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Action::Content { .. } => "xor",
+            Action::Link { .. } => "link",
+            Action::Rename { .. } => "rename",
+            Action::Placeholder { .. } => "placeholder",
+            Action::Target { .. } => "target",
+            Action::Skip { .. } => "skip",
+            Action::Fail { .. } => "fail"
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:<12}", self.name())?;
+
+        match *self {
+            Action::Content { ref path } => write!(f, "{:?}", path),
+            Action::Link { ref path, ref existing } => write!(f, "{:?} -> {:?}", path, existing),
+            Action::Rename { ref from, ref to } => write!(f, "{:?} -> {:?}", from, to),
+            Action::Placeholder { ref from, ref to } => write!(f, "{:?} -> {:?} (the encrypted name is too long)", from, to),
+            Action::Target { ref path, ref from, ref to } => write!(f, "{:?}: {:?} -> {:?}", path, from, to),
+            Action::Skip { ref path, ref reason } | Action::Fail { ref path, ref reason } => write!(f, "{:?}: {}", path, reason)
+        }
+    }
+}
+
+fn path_string(path : &Path) -> String {
+    path.to_string_lossy().into_owned()
+}
+
+fn json_escape(value : &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c)
+        }
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plans_print_as_text_and_json() {
+        let plan = Plan { actions : vec![
+            Action::Content { path : PathBuf::from("/root/a") },
+            Action::Rename { from : PathBuf::from("/root/a"), to : PathBuf::from("/root/26") },
+            Action::Skip { path : PathBuf::from("/root/l\"nk"), reason : String::from("symlinks are skipped") }
+        ] };
+
+        let mut text = Vec::new();
+        plan.write(&mut text, PlanFormat::Text).unwrap();
+        assert_eq!(String::from_utf8(text).unwrap(), "\
+xor         \"/root/a\"
+rename      \"/root/a\" -> \"/root/26\"
+skip        \"/root/l\\\"nk\": symlinks are skipped
+");
+
+        let mut json = Vec::new();
+        plan.write(&mut json, PlanFormat::Json).unwrap();
+        assert_eq!(String::from_utf8(json).unwrap(), "\
+[
+  {\"action\": \"xor\", \"path\": \"/root/a\"},
+  {\"action\": \"rename\", \"from\": \"/root/a\", \"to\": \"/root/26\"},
+  {\"action\": \"skip\", \"path\": \"/root/l\\\"nk\", \"reason\": \"symlinks are skipped\"}
+]
+");
+    }
+}
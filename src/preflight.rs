@@ -0,0 +1,83 @@
+use std::path::{Path, PathBuf};
+use rsfs::*;
+
+
+/// Recursively searches the supplied path and finds the size of the largest file.
+/// Symlinks aren't followed, so a link back up the tree can't send the search round in circles.
+pub fn get_largest_file_size<T: GenFS>(fs: &T, path : &Path) -> u64 {
+    let mut size : u64 = 0;
+
+    for_each_entry(fs, path, |_, metadata| {
+        // Check if the current file is the largest.
+        if metadata.is_file() && metadata.len() > size {
+            size = metadata.len();
+        }
+    });
+
+    size
+}
+
+/// Recursively searches the supplied path and finds the length of the longest file/directory name.
+/// Symlinks aren't followed, so a link back up the tree can't send the search round in circles.
+pub fn get_longest_name<T: GenFS>(fs: &T, path : &Path) -> usize {
+    let mut size : usize = 0;
+
+    for_each_entry(fs, path, |entry_path, _| {
+        // Check if the current entry name is the longest.
+        if let Some(name) = entry_path.file_name() {
+            if name.len() > size {
+                size = name.len();
+            }
+        }
+    });
+
+    size
+}
+
+/// Calls "f" with the path and metadata of "path" and of everything below it.
+/// Directories are searched with an explicit stack rather than recursion, so deep trees can't
+/// overflow the stack.
+fn for_each_entry<T, F>(fs: &T, path : &Path, mut f : F)
+    where T: GenFS, F: FnMut(&Path, &T::Metadata) {
+
+        let mut pending : Vec<PathBuf> = vec![path.to_path_buf()];
+
+        while let Some(path) = pending.pop() {
+            let metadata = match fs.symlink_metadata(&path) {
+                Ok(metadata) => metadata,
+                Err(err) => {
+                    info!("Failed to get metadata for path {:?} because: {}", path, err);
+                    continue;
+                }
+            };
+
+            f(&path, &metadata);
+
+            if metadata.is_dir() {
+                // Check the child files and directories too.
+                match fs.read_dir(&path) {
+                    Ok(entries) => pending.extend(entries.filter_map(|entry| entry.ok()).map(|entry| entry.path())),
+                    Err(err) => info!("Failed to read directory {:?} because: {}", path, err)
+                }
+            }
+        }
+    }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rsfs::mem::FS;
+    use std::io::Write;
+
+    #[test]
+    fn sizes_are_found_through_the_file_system() {
+        let fs = FS::new();
+        fs.create_dir_all("/root/a/much_longer_name").unwrap();
+        fs.create_file("/root/small").unwrap().write_all(b"data").unwrap();
+        fs.create_file("/root/a/much_longer_name/large").unwrap().write_all(&[0; 1000]).unwrap();
+
+        assert_eq!(get_largest_file_size(&fs, Path::new("/root")), 1000);
+        assert_eq!(get_longest_name(&fs, Path::new("/root")), 16);
+        assert_eq!(get_largest_file_size(&fs, Path::new("/missing")), 0);
+    }
+}
@@ -1,28 +1,168 @@
-
 use std::io;
 use std::io::{Write};
+use std::str::FromStr;
+
+const ERR_REFUSED_TERMINAL : &str = r#"refusing to write the encoded data to a terminal.
 
-const ERR_ENCODED_DATA_NOT_UTF8 : &'static str = r#"ERROR: Encoded data isn't printable.
+Encoded data is usually binary, which can garble the terminal.
+Use the "-o" option to write the output to a file, pipe it to another program, or choose another
+"terminal-output" rendering."#;
 
-The encoded data couldn't be encoded to valid utf8 and so couldn't be printed to the screen.
-Use the "-o" option to write the output directly to a file instead."#;
+/// Bytes per line when rendering hex on a terminal.
+const HEX_LINE_LEN : usize = 32;
+
+/// How output written to stdout is shown when stdout is a terminal. Anything else, such as a pipe
+/// or a redirected file, always gets the raw bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TerminalOutput {
+    /// Control characters and bytes outside of ascii are escaped the way `cat -v` does.
+    Escape,
+    /// Every byte is written as two lowercase hex digits.
+    Hex,
+    /// Nothing is written, and an error is returned before any input is read.
+    Refuse,
+    /// The raw bytes, whatever they do to the terminal.
+    Raw
+}
 
+impl FromStr for TerminalOutput {
+    type Err = String;
 
-pub struct StdoutWriter;
+    fn from_str(s : &str) -> Result<TerminalOutput, String> {
+        match s {
+            "escape" => Ok(TerminalOutput::Escape),
+            "hex" => Ok(TerminalOutput::Hex),
+            "refuse" => Ok(TerminalOutput::Refuse),
+            "raw" => Ok(TerminalOutput::Raw),
+            _ => Err(format!("unknown terminal output \"{}\"", s))
+        }
+    }
+}
+
+/// Writes to stdout, rendering the bytes safely when stdout is a terminal, see `TerminalOutput`.
+pub struct StdoutWriter {
+    rendering : TerminalOutput,
+    column : usize
+}
+
+impl StdoutWriter {
+
+    /// Creates a writer for stdout. Returns an error, without writing anything, if stdout is a
+    /// terminal and "terminal_output" refuses to write to one.
+    pub fn new(terminal_output : TerminalOutput) -> io::Result<StdoutWriter> {
+        let rendering = if stdout_is_terminal() { terminal_output } else { TerminalOutput::Raw };
+
+        if rendering == TerminalOutput::Refuse {
+            return Err(io::Error::other(ERR_REFUSED_TERMINAL));
+        }
+
+        Ok(StdoutWriter { rendering, column : 0 })
+    }
+}
 
 impl Write for StdoutWriter {
     fn write(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
-        match String::from_utf8(Vec::from(buf)) {
-            Ok(encoded) => {
-                let mut out = io::stdout();
-                out.write_all(encoded.as_bytes()).unwrap();
-                let _ = out.flush();
-            },
-            Err(e) => println!("{}\n\nDetails: {}", ERR_ENCODED_DATA_NOT_UTF8, e)
+        let out = io::stdout();
+        let mut out = out.lock();
+
+        match self.rendering {
+            TerminalOutput::Escape => out.write_all(&escape(buf))?,
+            TerminalOutput::Hex => out.write_all(&hex_lines(buf, &mut self.column))?,
+            TerminalOutput::Refuse | TerminalOutput::Raw => out.write_all(buf)?
         }
+
         Ok(buf.len())
     }
     fn flush(&mut self) -> Result<(), io::Error> {
-        Ok(())
+        io::stdout().flush()
+    }
+}
+
+impl Drop for StdoutWriter {
+    fn drop(&mut self) {
+        // Finish the last line of hex so the prompt isn't left on the end of it.
+        if self.column > 0 {
+            let _ = io::stdout().write_all(b"\n");
+        }
+    }
+}
+
+/// Renders the bytes like `cat -v`: control characters become "^" followed by a letter, bytes with
+/// the top bit set become "M-" followed by the rendering of the low 7 bits. Newlines and tabs are
+/// kept so text stays readable.
+fn escape(buf : &[u8]) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(buf.len());
+
+    for &byte in buf {
+        let mut low = byte;
+        if byte >= 0x80 {
+            escaped.extend_from_slice(b"M-");
+            low = byte & 0x7f;
+        }
+
+        match low {
+            b'\n' | b'\t' if byte < 0x80 => escaped.push(low),
+            0x7f => escaped.extend_from_slice(b"^?"),
+            0x00..=0x1f => escaped.extend_from_slice(&[b'^', low + 0x40]),
+            _ => escaped.push(low)
+        }
+    }
+
+    escaped
+}
+
+/// Renders the bytes as lowercase hex, `HEX_LINE_LEN` bytes to a line. "column" is the number of
+/// bytes already on the current line, and is carried between writes.
+fn hex_lines(buf : &[u8], column : &mut usize) -> Vec<u8> {
+    const DIGITS : &[u8; 16] = b"0123456789abcdef";
+    let mut lines = Vec::with_capacity(buf.len() * 2 + buf.len() / HEX_LINE_LEN + 1);
+
+    for &byte in buf {
+        lines.push(DIGITS[(byte >> 4) as usize]);
+        lines.push(DIGITS[(byte & 0x0f) as usize]);
+
+        *column += 1;
+        if *column == HEX_LINE_LEN {
+            lines.push(b'\n');
+            *column = 0;
+        }
+    }
+
+    lines
+}
+
+#[cfg(unix)]
+fn stdout_is_terminal() -> bool {
+    extern "C" {
+        fn isatty(fd : i32) -> i32;
+    }
+
+    // Safe, isatty only looks at the descriptor.
+    unsafe { isatty(1) == 1 }
+}
+
+#[cfg(not(unix))]
+fn stdout_is_terminal() -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn terminal_renderings_are_printable_and_split_safely() {
+        assert_eq!(escape(b"ok\n\t\x00\x1b\x7f\xe9\x8a\xff"), b"ok\n\t^@^[^?M-iM-^JM-^?".to_vec());
+
+        let bytes : Vec<u8> = (0..40).collect();
+        let mut column = 0;
+        let mut split = hex_lines(&bytes[..7], &mut column);
+        split.extend(hex_lines(&bytes[7..], &mut column));
+
+        let mut whole_column = 0;
+        assert_eq!(split, hex_lines(&bytes, &mut whole_column));
+        assert_eq!(column, 8);
+        assert_eq!(split.iter().filter(|&&b| b == b'\n').count(), 1);
+        assert!(split.starts_with(b"000102"));
     }
 }
@@ -0,0 +1,539 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+
+const HEX_ALPHABET : &[u8] = b"0123456789abcdef";
+
+const BASE64_ALPHABET : &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+const BASE64URL_ALPHABET : &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+const BASE32_ALPHABET : &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// The number of bytes of encoded text a `DecodingReader` reads at a time.
+const READ_CHUNK_SIZE : usize = 4 * 1024;
+
+/// How stream mode input is read, or its output written.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StreamEncoding {
+    /// The bytes as they are.
+    #[default]
+    Raw,
+    /// Lowercase hex, two characters per byte. Either case is read.
+    Hex,
+    /// RFC 4648 base64 with padding.
+    Base64,
+    /// RFC 4648 base64url without padding. Padding is accepted when reading.
+    Base64Url,
+    /// RFC 4648 base32 with padding. Either case is read.
+    Base32,
+    /// Ascii85, five characters per four bytes, with "z" standing for four zero bytes. The "<~"
+    /// and "~>" delimiters are accepted when reading but aren't written.
+    Ascii85
+}
+
+impl StreamEncoding {
+
+    /// The name used for the encoding on the command line.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            StreamEncoding::Raw => "raw",
+            StreamEncoding::Hex => "hex",
+            StreamEncoding::Base64 => "base64",
+            StreamEncoding::Base64Url => "base64url",
+            StreamEncoding::Base32 => "base32",
+            StreamEncoding::Ascii85 => "ascii85"
+        }
+    }
+
+    /// The alphabet of the encodings that write a fixed number of bits per character.
+    fn alphabet(&self) -> Option<Alphabet> {
+        let (chars, bits, padded_group, ignore_case) = match *self {
+            StreamEncoding::Hex => (HEX_ALPHABET, 4, 0, true),
+            StreamEncoding::Base64 => (BASE64_ALPHABET, 6, 4, false),
+            StreamEncoding::Base64Url => (BASE64URL_ALPHABET, 6, 0, false),
+            StreamEncoding::Base32 => (BASE32_ALPHABET, 5, 8, true),
+            StreamEncoding::Raw | StreamEncoding::Ascii85 => return None
+        };
+
+        Some(Alphabet { chars, bits, padded_group, ignore_case })
+    }
+}
+
+impl fmt::Display for StreamEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for StreamEncoding {
+    type Err = String;
+
+    fn from_str(s : &str) -> Result<StreamEncoding, String> {
+        match s {
+            "raw" => Ok(StreamEncoding::Raw),
+            "hex" => Ok(StreamEncoding::Hex),
+            "base64" => Ok(StreamEncoding::Base64),
+            "base64url" => Ok(StreamEncoding::Base64Url),
+            "base32" => Ok(StreamEncoding::Base32),
+            "ascii85" => Ok(StreamEncoding::Ascii85),
+            _ => Err(format!("\"{}\" isn't a stream encoding, expected raw, hex, base64, base64url, base32 or ascii85", s))
+        }
+    }
+}
+
+/// The characters of an encoding that writes "bits" bits per character.
+#[derive(Clone, Copy)]
+struct Alphabet {
+    chars : &'static [u8],
+    bits : u32,
+    /// The number of characters the output is padded to a multiple of with "=", or 0 for none.
+    padded_group : usize,
+    ignore_case : bool
+}
+
+impl Alphabet {
+
+    /// Maps every byte to its value in the alphabet, or to `INVALID`.
+    fn decode_table(&self) -> [u8; 256] {
+        let mut table = [INVALID; 256];
+        for (value, &c) in self.chars.iter().enumerate() {
+            table[c as usize] = value as u8;
+            if self.ignore_case {
+                table[c.to_ascii_uppercase() as usize] = value as u8;
+                table[c.to_ascii_lowercase() as usize] = value as u8;
+            }
+        }
+        table
+    }
+}
+
+const INVALID : u8 = 0xFF;
+
+/// Wraps a writer and encodes everything written through it as text.
+///
+/// The last few bytes can't be encoded until it's known no more are coming, so `finish` must be
+/// called once everything is written. It's called when the writer is dropped otherwise, but any
+/// error is then lost.
+pub struct EncodingWriter<W: Write> {
+    inner : W,
+    encoding : StreamEncoding,
+    wrap : usize,
+    column : usize,
+    written : usize,
+    buffer : u32,
+    bits : u32,
+    group : Vec<u8>,
+    finished : bool
+}
+
+impl<W: Write> EncodingWriter<W> {
+
+    /// Creates a writer that encodes everything onto one line, followed by a newline.
+    /// `StreamEncoding::Raw` passes the bytes straight through.
+    pub fn new(inner : W, encoding : StreamEncoding) -> EncodingWriter<W> {
+        EncodingWriter {
+            inner,
+            encoding,
+            wrap : 0,
+            column : 0,
+            written : 0,
+            buffer : 0,
+            bits : 0,
+            group : Vec::with_capacity(4),
+            finished : false
+        }
+    }
+
+    /// Breaks the encoded text into lines of "columns" characters, 0 leaves it on one line.
+    pub fn wrap(mut self, columns : usize) -> EncodingWriter<W> {
+        self.wrap = columns;
+        self
+    }
+
+    /// Encodes the bytes still held back, pads the text and ends the last line.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+
+        let mut text = Vec::new();
+        if let Some(alphabet) = self.encoding.alphabet() {
+            if self.bits > 0 {
+                // The last character is padded out with zero bits.
+                let value = (self.buffer << (alphabet.bits - self.bits)) & ((1 << alphabet.bits) - 1);
+                self.push(alphabet.chars[value as usize], &mut text);
+            }
+
+            if alphabet.padded_group > 0 {
+                while !self.written.is_multiple_of(alphabet.padded_group) {
+                    self.push(b'=', &mut text);
+                }
+            }
+        } else if !self.group.is_empty() {
+            // A partial group of n bytes is padded with zeros and written as n + 1 characters.
+            let len = self.group.len();
+            self.group.resize(4, 0);
+            let chars = ascii85_group(&self.group);
+            for &c in &chars[..len + 1] {
+                self.push(c, &mut text);
+            }
+        }
+
+        if self.column > 0 {
+            text.push(b'\n');
+            self.column = 0;
+        }
+
+        self.inner.write_all(&text)?;
+        self.inner.flush()
+    }
+
+    /// Adds a character to the text, starting a new line when it reaches the wrap width.
+    fn push(&mut self, c : u8, text : &mut Vec<u8>) {
+        text.push(c);
+        self.written += 1;
+        self.column += 1;
+
+        if self.wrap > 0 && self.column == self.wrap {
+            text.push(b'\n');
+            self.column = 0;
+        }
+    }
+}
+
+impl<W: Write> Write for EncodingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.encoding == StreamEncoding::Raw {
+            return self.inner.write(buf);
+        }
+
+        let mut text = Vec::with_capacity(buf.len() * 2 + 2);
+        match self.encoding.alphabet() {
+            Some(alphabet) => {
+                let mask = (1 << alphabet.bits) - 1;
+                for &byte in buf {
+                    self.buffer = (self.buffer << 8) | u32::from(byte);
+                    self.bits += 8;
+
+                    while self.bits >= alphabet.bits {
+                        self.bits -= alphabet.bits;
+                        let value = (self.buffer >> self.bits) & mask;
+                        self.push(alphabet.chars[value as usize], &mut text);
+                    }
+                    self.buffer &= (1 << self.bits) - 1;
+                }
+            },
+            None => {
+                for &byte in buf {
+                    self.group.push(byte);
+                    if self.group.len() < 4 {
+                        continue;
+                    }
+
+                    if self.group == [0; 4] {
+                        self.push(b'z', &mut text);
+                    } else {
+                        for &c in &ascii85_group(&self.group) {
+                            self.push(c, &mut text);
+                        }
+                    }
+                    self.group.clear();
+                }
+            }
+        }
+
+        self.inner.write_all(&text)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Drop for EncodingWriter<W> {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+/// Where a `DecodingReader` is in the delimiters of ascii85 text.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Ascii85State {
+    Start,
+    Opened,
+    Body,
+    Closing,
+    Ended
+}
+
+/// Wraps a reader of encoded text and decodes it as it's read. Whitespace anywhere in the text
+/// is skipped, and anything else that isn't part of the encoding gives an
+/// `io::ErrorKind::InvalidData` error.
+pub struct DecodingReader<R: Read> {
+    inner : R,
+    encoding : StreamEncoding,
+    table : [u8; 256],
+    buffer : u32,
+    bits : u32,
+    padding : bool,
+    group : u64,
+    group_len : usize,
+    ascii85 : Ascii85State,
+    offset : u64,
+    decoded : Vec<u8>,
+    pos : usize,
+    eof : bool
+}
+
+impl<R: Read> DecodingReader<R> {
+
+    /// Creates a reader for text in the given encoding. `StreamEncoding::Raw` passes the bytes
+    /// straight through.
+    pub fn new(inner : R, encoding : StreamEncoding) -> DecodingReader<R> {
+        DecodingReader {
+            inner,
+            encoding,
+            table : encoding.alphabet().map(|alphabet| alphabet.decode_table()).unwrap_or([INVALID; 256]),
+            buffer : 0,
+            bits : 0,
+            padding : false,
+            group : 0,
+            group_len : 0,
+            ascii85 : Ascii85State::Start,
+            offset : 0,
+            decoded : Vec::new(),
+            pos : 0,
+            eof : false
+        }
+    }
+
+    /// Decodes one character of text into `decoded`.
+    fn decode(&mut self, c : u8) -> io::Result<()> {
+        self.offset += 1;
+        if c.is_ascii_whitespace() {
+            return Ok(());
+        }
+
+        let alphabet = match self.encoding.alphabet() {
+            Some(alphabet) => alphabet,
+            None => return self.decode_ascii85(c)
+        };
+
+        if c == b'=' && self.encoding != StreamEncoding::Hex {
+            self.padding = true;
+            return Ok(());
+        }
+
+        let value = self.table[c as usize];
+        if value == INVALID || self.padding {
+            return Err(self.invalid(c));
+        }
+
+        self.buffer = (self.buffer << alphabet.bits) | u32::from(value);
+        self.bits += alphabet.bits;
+        if self.bits >= 8 {
+            self.bits -= 8;
+            self.decoded.push((self.buffer >> self.bits) as u8);
+            self.buffer &= (1 << self.bits) - 1;
+        }
+
+        Ok(())
+    }
+
+    fn decode_ascii85(&mut self, c : u8) -> io::Result<()> {
+        match (self.ascii85, c) {
+            (Ascii85State::Start, b'<') => self.ascii85 = Ascii85State::Opened,
+            (Ascii85State::Opened, b'~') => self.ascii85 = Ascii85State::Body,
+            (Ascii85State::Start, b'~') | (Ascii85State::Body, b'~') => self.ascii85 = Ascii85State::Closing,
+            (Ascii85State::Closing, b'>') => self.ascii85 = Ascii85State::Ended,
+            (Ascii85State::Start, b'z') | (Ascii85State::Body, b'z') if self.group_len == 0 => {
+                self.ascii85 = Ascii85State::Body;
+                self.decoded.extend_from_slice(&[0; 4]);
+            },
+            (Ascii85State::Start, b'!'..=b'u') | (Ascii85State::Body, b'!'..=b'u') => {
+                self.ascii85 = Ascii85State::Body;
+                self.group = self.group * 85 + u64::from(c - b'!');
+                self.group_len += 1;
+
+                if self.group_len == 5 {
+                    self.push_ascii85_group(4)?;
+                }
+            },
+            _ => return Err(self.invalid(c))
+        }
+
+        Ok(())
+    }
+
+    /// Adds the first "len" bytes of the current ascii85 group to `decoded`.
+    fn push_ascii85_group(&mut self, len : usize) -> io::Result<()> {
+        if self.group > u64::from(u32::MAX) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("the ascii85 group ending at offset {} is too large", self.offset)));
+        }
+
+        let bytes = [(self.group >> 24) as u8, (self.group >> 16) as u8, (self.group >> 8) as u8, self.group as u8];
+        self.decoded.extend_from_slice(&bytes[..len]);
+        self.group = 0;
+        self.group_len = 0;
+        Ok(())
+    }
+
+    /// Checks the text didn't end part way through, and decodes the last partial ascii85 group.
+    fn finish(&mut self) -> io::Result<()> {
+        let truncated = || io::Error::new(io::ErrorKind::InvalidData, "the encoded input ends part way through a byte");
+
+        match self.encoding.alphabet() {
+            // Whatever is left over must be the zero padding of the last character.
+            Some(alphabet) => if self.bits >= alphabet.bits || self.buffer != 0 {
+                return Err(truncated());
+            },
+            None => {
+                if self.ascii85 == Ascii85State::Opened || self.ascii85 == Ascii85State::Closing || self.group_len == 1 {
+                    return Err(truncated());
+                }
+
+                if self.group_len > 0 {
+                    // A partial group of n characters is padded with "u" and gives n - 1 bytes.
+                    let len = self.group_len - 1;
+                    for _ in self.group_len..5 {
+                        self.group = self.group * 85 + 84;
+                    }
+                    self.push_ascii85_group(len)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn invalid(&self, c : u8) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, format!("{:?} at offset {} isn't valid {}", c as char, self.offset - 1, self.encoding))
+    }
+}
+
+impl<R: Read> Read for DecodingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.encoding == StreamEncoding::Raw {
+            return self.inner.read(buf);
+        }
+
+        while self.pos == self.decoded.len() {
+            if self.eof {
+                return Ok(0);
+            }
+
+            self.decoded.clear();
+            self.pos = 0;
+
+            let mut text = [0; READ_CHUNK_SIZE];
+            let n = match self.inner.read(&mut text) {
+                Ok(n) => n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e)
+            };
+
+            if n == 0 {
+                self.eof = true;
+                self.finish()?;
+            }
+
+            for &c in &text[..n] {
+                self.decode(c)?;
+            }
+        }
+
+        let n = buf.len().min(self.decoded.len() - self.pos);
+        buf[..n].copy_from_slice(&self.decoded[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Encodes four bytes as five ascii85 characters.
+fn ascii85_group(bytes : &[u8]) -> [u8; 5] {
+    let mut value = (u32::from(bytes[0]) << 24) | (u32::from(bytes[1]) << 16) | (u32::from(bytes[2]) << 8) | u32::from(bytes[3]);
+    let mut chars = [0; 5];
+
+    for c in chars.iter_mut().rev() {
+        *c = (value % 85) as u8 + b'!';
+        value /= 85;
+    }
+
+    chars
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const ENCODINGS : [StreamEncoding; 6] = [StreamEncoding::Raw, StreamEncoding::Hex, StreamEncoding::Base64, StreamEncoding::Base64Url, StreamEncoding::Base32, StreamEncoding::Ascii85];
+
+    fn encode(bytes : &[u8], encoding : StreamEncoding, wrap : usize) -> Vec<u8> {
+        let mut text = Vec::new();
+        {
+            let mut writer = EncodingWriter::new(&mut text, encoding).wrap(wrap);
+            // Written a few bytes at a time so groups are split across writes.
+            for chunk in bytes.chunks(3) {
+                writer.write_all(chunk).unwrap();
+            }
+            writer.finish().unwrap();
+        }
+        text
+    }
+
+    fn decode(text : &[u8], encoding : StreamEncoding) -> io::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        DecodingReader::new(Cursor::new(text), encoding).read_to_end(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    #[test]
+    fn encodings_match_known_vectors() {
+        let vectors : [(StreamEncoding, &[u8], &[u8]); 7] = [
+            (StreamEncoding::Hex, b"foobar", b"666f6f626172\n"),
+            (StreamEncoding::Base64, b"fooba", b"Zm9vYmE=\n"),
+            (StreamEncoding::Base64Url, b"\xfb\xff", b"-_8\n"),
+            (StreamEncoding::Base32, b"foob", b"MZXW6YQ=\n"),
+            (StreamEncoding::Ascii85, b"Man sure.", b"9jqo^F*2M7/c\n"),
+            (StreamEncoding::Ascii85, b"\0\0\0\0\0", b"z!!\n"),
+            (StreamEncoding::Hex, b"", b"")
+        ];
+
+        for &(encoding, plain, text) in &vectors {
+            assert_eq!(encode(plain, encoding, 0), text);
+            assert_eq!(decode(text, encoding).unwrap(), plain);
+        }
+
+        assert_eq!(decode(b" 66 6F\n6f\t", StreamEncoding::Hex).unwrap(), b"foo");
+        assert_eq!(decode(b"<~9jqo^\nF*2M7/c~>\n", StreamEncoding::Ascii85).unwrap(), b"Man sure.");
+        assert_eq!(decode(b"mzxw6yq=", StreamEncoding::Base32).unwrap(), b"foob");
+    }
+
+    #[test]
+    fn every_encoding_round_trips_when_wrapped() {
+        let bytes : Vec<u8> = (0..1000).map(|i| (i * 7 % 256) as u8).collect();
+
+        for &encoding in &ENCODINGS {
+            for &len in &[0, 1, 2, 3, 4, 5, 999] {
+                let text = encode(&bytes[..len], encoding, 76);
+                if encoding != StreamEncoding::Raw {
+                    assert!(text.split(|&c| c == b'\n').all(|line| line.len() <= 76));
+                }
+                assert_eq!(decode(&text, encoding).unwrap(), &bytes[..len]);
+                assert_eq!(encoding.name().parse::<StreamEncoding>().unwrap(), encoding);
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_or_truncated_text_is_an_error() {
+        for &(text, encoding) in &[(&b"abc"[..], StreamEncoding::Hex), (b"ab\xffcd", StreamEncoding::Hex), (b"Zm9=v", StreamEncoding::Base64),
+                                   (b"M", StreamEncoding::Base32), (b"9jqo^F", StreamEncoding::Ascii85), (b"s8W-\"", StreamEncoding::Ascii85)] {
+            assert_eq!(decode(text, encoding).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        }
+    }
+}
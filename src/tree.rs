@@ -0,0 +1,2055 @@
+use std::collections::HashSet;
+use std::ffi::{OsStr, OsString};
+use std::fmt::{self, Debug};
+use std::str::FromStr;
+use std::io;
+use std::path::{Component, Path, PathBuf, is_separator};
+use hex::{FromHex, FromHexError};
+use rsfs::*;
+use keystream::{Keystream, check_key};
+use atomic_file::{rewrite_file, rewrite_file_then, link_file, link_file_then, replace_symlink, replace_symlink_then, is_temp_file};
+use journal::{Journal, JournalFile, JournalState, create_journal, reopen_journal, read_journal, remove_journal, remove_leftover_temps, roll_back, is_journal_file};
+use marker::{check_marker, read_marker, write_marker, remove_marker, is_marker_file};
+use name_codec::NameCodec;
+use manifest::{NameManifest, DEFAULT_MAX_NAME_LEN, placeholder_for, is_placeholder, is_manifest_file, manifest_path, remove_manifest};
+use plan::{Action, Plan};
+use links::{HardLinks, IdentifyFn};
+use filter::{Filter, Pattern, Selection, is_ignore_file};
+use error::{XorError, Operation};
+
+/// The mode is used in conjunction with the "recursive" option and determines how file names
+/// will be processed when renaming files.
+/// When in "encrypt" mode, file names are XOR'd then hexified.
+/// When in "decrypt" mode, file names are unhexified then XOR'd.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    Encrypt,
+    Decrypt
+}
+
+/// Which parts of a tree a recursive run encrypts or decrypts. Symlinks count as files.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Parts {
+    /// The contents of files.
+    pub contents : bool,
+    /// The names of files and symlinks.
+    pub file_names : bool,
+    /// The names of directories.
+    pub dir_names : bool
+}
+
+impl Parts {
+
+    /// Every part of the tree.
+    pub fn all() -> Parts {
+        Parts { contents : true, file_names : true, dir_names : true }
+    }
+
+    /// Returns true if the names of directories, or of files when "is_dir" isn't set, are
+    /// transformed.
+    pub fn names(&self, is_dir : bool) -> bool {
+        if is_dir { self.dir_names } else { self.file_names }
+    }
+}
+
+impl Default for Parts {
+    fn default() -> Parts {
+        Parts::all()
+    }
+}
+
+/// The parts as a comma separated list, as used in the marker and journal, or "none".
+impl fmt::Display for Parts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names : Vec<&str> = [(self.contents, "contents"), (self.file_names, "file-names"), (self.dir_names, "dir-names")]
+            .iter()
+            .filter(|&&(included, _)| included)
+            .map(|&(_, name)| name)
+            .collect();
+
+        if names.is_empty() {
+            write!(f, "none")
+        } else {
+            write!(f, "{}", names.join(","))
+        }
+    }
+}
+
+impl FromStr for Parts {
+    type Err = String;
+
+    fn from_str(s : &str) -> Result<Parts, String> {
+        let mut parts = Parts { contents : false, file_names : false, dir_names : false };
+
+        if s == "none" {
+            return Ok(parts);
+        }
+
+        for name in s.split(',') {
+            match name {
+                "contents" => parts.contents = true,
+                "file-names" => parts.file_names = true,
+                "dir-names" => parts.dir_names = true,
+                _ => return Err(format!("\"{}\" isn't a part of a tree, expected contents, file-names or dir-names", name))
+            }
+        }
+
+        Ok(parts)
+    }
+}
+
+/// The prefix given to entries whose decrypted names aren't safe to use, when quarantining them.
+pub const QUARANTINE_PREFIX : &str = "quarantined-";
+
+/// A step of a walk, see `Walker::walk`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Work {
+    /// Process an entry found in a directory, "depth" directories below the root.
+    Entry { path : PathBuf, kind : EntryKind, depth : usize },
+    /// Queue up the entries of a directory followed by its `Leave`.
+    Contents { path : PathBuf, depth : usize, rename : bool },
+    /// Every entry of a directory has been processed, so it can be renamed if "rename" is set.
+    /// "failures" is how many failures there were before its entries were processed.
+    Leave { path : PathBuf, rename : bool, failures : usize }
+}
+
+/// What a walk does with an entry named in a symlink target, see `Walker::rewrite_target`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum TargetName {
+    /// The entry is renamed, so its name in the target is transformed too.
+    Renamed,
+    /// The entry keeps its name, but entries below it may still be renamed.
+    Kept,
+    /// Neither the entry nor anything below it is renamed.
+    Untouched
+}
+
+/// The type of a directory entry, as far as a walk is concerned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum EntryKind {
+    Dir,
+    File,
+    Symlink,
+    Other
+}
+
+impl EntryKind {
+    fn of<F: rsfs::FileType>(file_type : &F) -> EntryKind {
+        if file_type.is_dir() {
+            EntryKind::Dir
+        } else if file_type.is_file() {
+            EntryKind::File
+        } else if file_type.is_symlink() {
+            EntryKind::Symlink
+        } else {
+            EntryKind::Other
+        }
+    }
+}
+
+/// What a recursive run does with the symlinks it finds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    /// Symlinks are left as they are.
+    Skip,
+    /// Symlinks are renamed, their targets are left as they are.
+    Rename,
+    /// Symlinks are renamed and the names in relative targets are encrypted or decrypted the same
+    /// way, so the links still resolve afterwards. Absolute targets, and the part of a target that
+    /// leaves the tree, are left as they are.
+    Rewrite,
+    /// Symlinks are processed as the file or directory they point at, then renamed. Anything that
+    /// can be reached through more than one path is only XOR'd once.
+    Follow
+}
+
+impl FromStr for SymlinkPolicy {
+    type Err = String;
+
+    fn from_str(s : &str) -> Result<SymlinkPolicy, String> {
+        match s {
+            "skip" => Ok(SymlinkPolicy::Skip),
+            "rename" => Ok(SymlinkPolicy::Rename),
+            "rewrite" => Ok(SymlinkPolicy::Rewrite),
+            "follow" => Ok(SymlinkPolicy::Follow),
+            _ => Err(format!("\"{}\" isn't a symlink policy, expected skip, rename, rewrite or follow", s))
+        }
+    }
+}
+
+/// Creates a symlink at the second path pointing at the first.
+/// `rsfs` only offers this through `rsfs::unix_ext::GenFSExt`, so it's supplied by the caller,
+/// see `create_symlink`.
+pub type SymlinkFn<T> = fn(&T, &Path, &Path) -> io::Result<()>;
+
+/// Creates symlinks on any file system that supports them.
+#[cfg(unix)]
+pub fn create_symlink<T: GenFS + rsfs::unix_ext::GenFSExt>(fs: &T, target : &Path, link : &Path) -> io::Result<()> {
+    fs.symlink(target, link)
+}
+
+/// What a recursive run did, and everything that failed along the way.
+#[derive(Debug, Default)]
+pub struct Summary {
+    /// The number of files whose contents were XOR'd.
+    pub contents : usize,
+    /// The number of entries that were renamed.
+    pub renamed : usize,
+    /// The number of entries that were left alone on purpose.
+    pub skipped : usize,
+    /// Every failure, in the order they happened.
+    pub failures : Vec<XorError>,
+    /// Whether the run stopped at the first failure rather than finishing the tree.
+    pub stopped : bool
+}
+
+impl Summary {
+
+    /// Returns true if nothing failed.
+    pub fn succeeded(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    /// Turns the first failure, if there was one, into an error.
+    pub fn into_result(self) -> io::Result<()> {
+        match self.failures.into_iter().next() {
+            Some(failure) => Err(failure.into()),
+            None => Ok(())
+        }
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} file(s) XOR'd, {} entr{} renamed, {} skipped, {} failed",
+               self.contents, self.renamed, if self.renamed == 1 { "y" } else { "ies" }, self.skipped, self.failures.len())?;
+        if self.stopped {
+            write!(f, ", stopped at the first failure")?;
+        }
+
+        for failure in &self.failures {
+            write!(f, "\n  {}", failure)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Carries the settings and state shared by every step of a recursive run.
+pub struct Walker<'a, T: GenFS + 'a> {
+    fs : &'a T,
+    key : &'a [u8],
+    mode : Mode,
+    name_codec : NameCodec,
+    parts : Parts,
+    max_name_len : usize,
+    force : bool,
+    symlinks : SymlinkPolicy,
+    quarantine : bool,
+    keep_going : bool,
+    filter : Filter,
+    min_depth : usize,
+    max_depth : usize,
+    one_file_system : Option<IdentifyFn>,
+    create_symlink : Option<SymlinkFn<T>>,
+    journal : Option<Journal<JournalFile<T>>>,
+    completed : Option<JournalState>,
+    hard_links : Option<HardLinks>,
+    linked : HashSet<PathBuf>,
+    root : Option<PathBuf>,
+    root_device : Option<u64>,
+    pending : Vec<Work>,
+    visited : HashSet<PathBuf>,
+    manifest : Option<NameManifest>,
+    unrestored_placeholders : usize,
+    plan : Option<Plan>,
+    summary : Summary
+}
+
+impl<'a, T: GenFS + 'a> Walker<'a, T> {
+
+    /// Creates a walker that records nothing, processes every entry it visits, encodes names as
+    /// hex and renames symlinks without following them.
+    ///
+    /// # Panics
+    ///
+    /// The walk panics if the key is empty, see `keystream::check_key`.
+    pub fn new(fs: &'a T, key : &'a [u8], mode : Mode) -> Walker<'a, T> {
+        Walker {
+            fs,
+            key,
+            mode,
+            name_codec : NameCodec::Hex,
+            parts : Parts::all(),
+            max_name_len : DEFAULT_MAX_NAME_LEN,
+            force : false,
+            symlinks : SymlinkPolicy::Rename,
+            quarantine : false,
+            keep_going : false,
+            filter : Filter::new(),
+            min_depth : 0,
+            max_depth : usize::MAX,
+            one_file_system : None,
+            create_symlink : None,
+            journal : None,
+            completed : None,
+            hard_links : None,
+            linked : HashSet::new(),
+            root : None,
+            root_device : None,
+            pending : Vec::new(),
+            visited : HashSet::new(),
+            manifest : None,
+            unrestored_placeholders : 0,
+            plan : None,
+            summary : Summary::default()
+        }
+    }
+
+    /// Sets how encrypted names are encoded, see `NameCodec`.
+    /// When `run` decrypts a marked tree the encoding recorded in its marker is used instead.
+    pub fn name_codec(mut self, name_codec : NameCodec) -> Walker<'a, T> {
+        self.name_codec = name_codec;
+        self
+    }
+
+    /// Sets which parts of the tree are encrypted or decrypted, every part by default.
+    /// When `run` decrypts a marked tree the parts recorded in its marker are used instead.
+    pub fn parts(mut self, parts : Parts) -> Walker<'a, T> {
+        self.parts = parts;
+        self
+    }
+
+    /// Sets the longest encrypted name, in bytes, that's used as is. Longer names are replaced by a
+    /// placeholder and kept in the tree's `NameManifest` instead.
+    pub fn max_name_len(mut self, max_name_len : usize) -> Walker<'a, T> {
+        self.max_name_len = max_name_len;
+        self
+    }
+
+    /// Allows `run` to decrypt a tree that isn't marked as encrypted.
+    pub fn force(mut self, force : bool) -> Walker<'a, T> {
+        self.force = force;
+        self
+    }
+
+    /// Sets what's done with symlinks, see `SymlinkPolicy`.
+    /// `SymlinkPolicy::Rewrite` also needs `creating_symlinks_with`, otherwise rewriting each link
+    /// fails.
+    pub fn symlinks(mut self, symlinks : SymlinkPolicy) -> Walker<'a, T> {
+        self.symlinks = symlinks;
+        self
+    }
+
+    /// Renames entries whose decrypted names aren't safe, see `check_name`, by adding
+    /// `QUARANTINE_PREFIX` to their encrypted names. Without this they're reported and skipped.
+    pub fn quarantine(mut self, quarantine : bool) -> Walker<'a, T> {
+        self.quarantine = quarantine;
+        self
+    }
+
+    /// Leaves alone the entries fewer than "min_depth" directories below the root, the root's
+    /// own entries being at depth 1. Directories above that depth keep their names, but their
+    /// entries are still walked.
+    pub fn min_depth(mut self, min_depth : usize) -> Walker<'a, T> {
+        self.min_depth = min_depth;
+        self
+    }
+
+    /// Doesn't walk below "max_depth" directories from the root, the root's own entries being at
+    /// depth 1. Directories at the maximum depth are still renamed, but their entries are left
+    /// alone.
+    pub fn max_depth(mut self, max_depth : usize) -> Walker<'a, T> {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Leaves alone the directories on a different file system to the root, such as mount
+    /// points, and doesn't follow symlinks onto one. The file system of a path is looked up with
+    /// "identify", see `links::disk_file_id`.
+    pub fn one_file_system(mut self, identify : IdentifyFn) -> Walker<'a, T> {
+        self.one_file_system = Some(identify);
+        self
+    }
+
+    /// Supplies the function used to create symlinks when their targets are rewritten.
+    pub fn creating_symlinks_with(mut self, create_symlink : SymlinkFn<T>) -> Walker<'a, T> {
+        self.create_symlink = Some(create_symlink);
+        self
+    }
+
+    /// Processes everything below "root" as a single run.
+    ///
+    /// The tree's marker is checked first so it's never XOR'd twice, see `check_marker`. Every
+    /// change is recorded in a journal at the root while the run is in progress, and if a journal
+    /// from an interrupted run is already there nothing is changed and an
+    /// `io::ErrorKind::AlreadyExists` error is returned. Once finished the tree is marked as
+    /// encrypted, along with the name encoding, or unmarked when decrypting.
+    /// Failures inside the tree don't make this return an error, they're collected in the
+    /// summary instead, and the journal is kept so the run can be resumed or rolled back.
+    pub fn run(mut self, root : &Path) -> Result<Summary, XorError> {
+        self.prepare(root)?;
+
+        let journal = create_journal(self.fs, root, self.mode, self.name_codec, self.parts)
+            .map_err(|e| XorError::io(Operation::StartJournal, root, e))?;
+        self.journal = Some(journal);
+
+        self.encrypt_path(root);
+        self.finish(root)
+    }
+
+    /// Works out everything `run` would do below "root" without changing anything: the files
+    /// whose contents would be XOR'd, every rename, and the entries that would be skipped or fail.
+    pub fn plan(mut self, root : &Path) -> Result<Plan, XorError> {
+        self.prepare(root)?;
+
+        self.plan = Some(Plan::new());
+        self.encrypt_path(root);
+
+        Ok(self.plan.unwrap_or_default())
+    }
+
+    /// The files whose contents a run would XOR, the ones the filter, the depth limits and the
+    /// other options select, found without changing anything. Hard links are looked for among
+    /// these, see `HardLinks::find`.
+    pub fn selected_files(self, root : &Path) -> Result<Vec<PathBuf>, XorError> {
+        let plan = self.plan(root)?;
+
+        Ok(plan.actions.into_iter()
+            .filter_map(|action| match action {
+                Action::Content { path } => Some(path),
+                _ => None
+            })
+            .collect())
+    }
+
+    /// Checks the tree's marker, and picks up the name encoding and parts from it when decrypting.
+    fn prepare(&mut self, root : &Path) -> Result<(), XorError> {
+        check_marker(self.fs, root, self.mode, self.force).map_err(|e| XorError::io(Operation::CheckMarker, root, e))?;
+
+        if self.mode == Mode::Decrypt {
+            if let Some(marker) = read_marker(self.fs, root).map_err(|e| XorError::io(Operation::CheckMarker, root, e))? {
+                self.name_codec = marker.name_codec;
+                self.parts = marker.parts;
+            }
+            self.read_manifest(root)?;
+        }
+
+        Ok(())
+    }
+
+    /// Finishes a run that was interrupted, skipping the changes recorded in its journal.
+    /// The run continues in the mode, and with the name encoding and parts, it was started with,
+    /// whatever the walker was created with.
+    pub fn resume(mut self, root : &Path) -> Result<Summary, XorError> {
+        let read_error = |e| XorError::io(Operation::ReadJournal, root, e);
+        let completed = match read_journal(self.fs, root).map_err(read_error)? {
+            Some(completed) => completed,
+            None => return Err(read_error(io::Error::new(io::ErrorKind::NotFound, "there is no interrupted run to resume")))
+        };
+
+        if completed.rollback_started() {
+            return Err(read_error(io::Error::other("the interrupted run is being rolled back, finish the rollback instead")));
+        }
+
+        self.mode = completed.mode;
+        self.name_codec = completed.name_codec;
+        self.parts = completed.parts;
+        if self.mode == Mode::Decrypt {
+            self.read_manifest(root)?;
+        }
+
+        let mut journal = reopen_journal(self.fs, root).map_err(read_error)?;
+        remove_leftover_temps(self.fs, root, &completed, &mut journal).map_err(|e| XorError::io(Operation::RemoveTemp, root, e))?;
+        self.journal = Some(journal);
+        self.completed = Some(completed);
+
+        self.encrypt_path(root);
+        self.finish(root)
+    }
+
+    /// Only processes the entries matching the pattern, see `Filter::include`.
+    pub fn include(mut self, pattern : Pattern) -> Walker<'a, T> {
+        self.filter.include(pattern);
+        self
+    }
+
+    /// Leaves the entries matching the pattern alone, see `Filter::exclude`.
+    pub fn exclude(mut self, pattern : Pattern) -> Walker<'a, T> {
+        self.filter.exclude(pattern);
+        self
+    }
+
+    /// Undoes the changes made by a run that was interrupted, using its journal.
+    pub fn roll_back(self, root : &Path) -> Result<(), XorError> {
+        roll_back(self.fs, root, self.key, self.create_symlink).map_err(|e| XorError::io(Operation::RollBack, root, e))
+    }
+
+    /// Reads the tree's name manifest before anything is changed. The manifest is XOR'd against
+    /// the key, so one that won't parse was almost certainly encrypted with a different key.
+    fn read_manifest(&mut self, root : &Path) -> Result<(), XorError> {
+        match NameManifest::read(self.fs, root, self.key) {
+            Ok(manifest) => {
+                self.manifest = Some(manifest);
+                Ok(())
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::InvalidData => Err(XorError::KeyMismatch {
+                path : manifest_path(root),
+                reason : String::from("the name manifest can't be read with this key")
+            }),
+            Err(e) => Err(XorError::io(Operation::ReadManifest, root, e))
+        }
+    }
+
+    /// Carries on past failures, collecting them in the summary, rather than stopping at the
+    /// first one.
+    pub fn keep_going(mut self, keep_going : bool) -> Walker<'a, T> {
+        self.keep_going = keep_going;
+        self
+    }
+
+    /// Records every change in the given journal before it's made.
+    pub fn with_journal(mut self, journal : Journal<JournalFile<T>>) -> Walker<'a, T> {
+        self.journal = Some(journal);
+        self
+    }
+
+    /// Skips the changes an interrupted run already made, as read from its journal.
+    pub fn resuming(mut self, completed : JournalState) -> Walker<'a, T> {
+        self.completed = Some(completed);
+        self
+    }
+
+    /// XOR's the contents of each hard linked file once, see `HardLinks`.
+    /// Without this every link is treated as a separate file, so a file with two links in the tree
+    /// has its contents XOR'd twice and left as they were.
+    pub fn with_hard_links(mut self, hard_links : HardLinks) -> Walker<'a, T> {
+        self.hard_links = Some(hard_links);
+        self
+    }
+
+    /// Encrypts or decrypts everything below the given directory, the directory itself isn't renamed.
+    pub fn encrypt_path(&mut self, p : &Path) {
+        if self.root.is_none() {
+            self.root = Some(p.to_path_buf());
+        }
+
+        self.first_visit(p);
+        self.pending.push(Work::Contents { path : p.to_path_buf(), depth : 0, rename : false });
+        self.walk();
+    }
+
+    /// Encrypts or decrypts a single directory entry according to its type.
+    /// Temporary files left behind by an interrupted run and the journal are left alone, and so
+    /// are the entries the filter or the depth limits leave out, see `Filter`.
+    pub fn xor_entry<E: rsfs::DirEntry>(&mut self, entry : &E) {
+        match entry.file_type() {
+            Ok(entry_type) => {
+                let path = entry.path();
+                let depth = self.depth_of(&path);
+                self.pending.push(Work::Entry { path, kind : EntryKind::of(&entry_type), depth });
+                self.walk();
+            },
+            Err(err) => info!("Failed to get filetype for DirEntry {:?} because: {}", entry, err)
+        }
+    }
+
+    /// XOR's the contents of a file in place then renames it.
+    /// The contents are replaced atomically, see `rewrite_file`, and the file is only renamed once
+    /// its new contents are safely on disk.
+    pub fn xor_file(&mut self, path : &Path) {
+        debug!("Encrypting file {:?}", path);
+
+        if self.already_renamed(path) {
+            debug!("Skipping {:?} which was processed by the interrupted run", path);
+            return;
+        }
+
+        if !self.first_visit(path) {
+            debug!("Not XORing {:?} again, it was reached through a symlink", path);
+        } else if !self.xor_contents(path) {
+            return;
+        }
+
+        self.rename_entry(path);
+    }
+
+    /// Handles a symlink according to the symlink policy, see `SymlinkPolicy`.
+    pub fn xor_symlink(&mut self, path : &Path) {
+        let depth = self.depth_of(path);
+        self.visit_symlink(path, depth);
+        self.walk();
+    }
+
+    /// Recursively encrypts or decrypts the contents of a directory then renames the directory.
+    pub fn xor_dir(&mut self, path : &Path) {
+        let depth = self.depth_of(path);
+        self.visit_dir(path, depth);
+        self.walk();
+    }
+
+    /// Works through the pending steps until the walk is finished.
+    /// Directories are walked with a queue of steps rather than by recursion, so however deep the
+    /// tree is the stack can't overflow. The latest steps are taken first, so the entries of a
+    /// directory are all finished before it's renamed.
+    fn walk(&mut self) {
+        while let Some(work) = self.pending.pop() {
+            match work {
+                Work::Entry { path, kind, depth } => self.visit_entry(path, kind, depth),
+                Work::Contents { path, depth, rename } => self.visit_contents(&path, depth, rename),
+                Work::Leave { path, rename, failures } => {
+                    self.filter.leave_dir();
+                    // A renamed directory is taken as finished when a run is resumed, so one with
+                    // failures beneath it keeps its name for the resumed run to go back into.
+                    if rename && self.summary.failures.len() > failures {
+                        debug!("Not renaming {:?} because some of its entries failed", path);
+                    } else if rename {
+                        self.rename_entry(&path);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Processes a directory entry according to its type, see `xor_entry`.
+    fn visit_entry(&mut self, path : PathBuf, kind : EntryKind, depth : usize) {
+        if is_reserved_file(&path, depth == 1) {
+            return;
+        }
+
+        // They're removed when the journal that may refer to them is resumed or rolled back.
+        if is_temp_file(&path) {
+            debug!("Leaving the temporary file {:?} alone", path);
+            return;
+        }
+
+        let is_dir = kind == EntryKind::Dir;
+
+        if is_dir && self.on_other_file_system(&path) {
+            debug!("Skipping {:?} which is on another file system", path);
+            self.skipped(path, String::from("it's on another file system"));
+            return;
+        }
+
+        match self.select(&path, is_dir) {
+            Selection::Selected if depth >= self.min_depth => (),
+            Selection::Excluded(reason) => {
+                debug!("Skipping {:?} because {}", path, reason);
+                self.skipped(path, reason);
+                return;
+            },
+            selection => {
+                // The directory keeps its name, but entries further down may still be processed.
+                if is_dir {
+                    if self.first_visit(&path) {
+                        self.pending.push(Work::Contents { path, depth, rename : false });
+                    }
+                } else {
+                    let reason = match selection {
+                        Selection::NotIncluded => "it doesn't match an include pattern",
+                        _ => "it's above the minimum depth"
+                    };
+                    debug!("Skipping {:?} because {}", path, reason);
+                    self.skipped(path, String::from(reason));
+                }
+                return;
+            }
+        }
+
+        match kind {
+            EntryKind::Dir => self.visit_dir(&path, depth),
+            EntryKind::File => self.xor_file(&path),
+            EntryKind::Symlink => self.visit_symlink(&path, depth),
+            EntryKind::Other => ()
+        }
+    }
+
+    /// Handles a symlink according to the symlink policy. A followed directory is queued, and the
+    /// symlink is renamed once the directory's entries are finished.
+    fn visit_symlink(&mut self, path : &Path, depth : usize) {
+        debug!("Encrypting symlink {:?}", path);
+
+        if self.already_renamed(path) {
+            debug!("Skipping {:?} which was processed by the interrupted run", path);
+            return;
+        }
+
+        match self.symlinks {
+            SymlinkPolicy::Skip => {
+                debug!("Skipping symlink {:?}", path);
+                self.skipped(path.to_path_buf(), String::from("symlinks are skipped"));
+                return;
+            },
+            SymlinkPolicy::Rename => (),
+            SymlinkPolicy::Rewrite => {
+                if !self.rewrite_link_target(path) {
+                    return;
+                }
+            },
+            SymlinkPolicy::Follow => {
+                if self.follow_link(path, depth) {
+                    return;
+                }
+            }
+        }
+
+        self.rename_entry(path);
+    }
+
+    /// Queues the contents of a directory, which is renamed once they're finished.
+    fn visit_dir(&mut self, path : &Path, depth : usize) {
+        debug!("Encrypting dir {:?}", path);
+
+        // Directories are renamed after their contents, so a renamed one is already finished.
+        if self.already_renamed(path) {
+            debug!("Skipping {:?} which was processed by the interrupted run", path);
+            return;
+        }
+
+        if self.first_visit(path) {
+            self.pending.push(Work::Contents { path : path.to_path_buf(), depth, rename : true });
+        } else {
+            debug!("Not processing the contents of {:?} again, they were reached through a symlink", path);
+            self.rename_entry(path);
+        }
+    }
+
+    /// Renames a directory entry.
+    /// When "mode" is Mode::Encrypt, the name of the entry is XOR'd then encoded, as hex by default.
+    /// When "mode" is Mode::Decrypt, the name of the entry is decoded then XOR'd.
+    /// Names that aren't among the parts being transformed are left as they are, see `parts`.
+    pub fn rename_entry(&mut self, path : &Path) {
+        let is_dir = self.fs.symlink_metadata(path).map(|metadata| metadata.is_dir()).unwrap_or(false);
+        if !self.parts.names(is_dir) {
+            return;
+        }
+
+        if let Some(original_name) = path.file_name() {
+            debug!("original_name: {:?}", original_name);
+
+            let restoring_placeholder = self.mode == Mode::Decrypt && is_placeholder(original_name);
+            if restoring_placeholder {
+                self.unrestored_placeholders += 1;
+            }
+
+            let mut replaced_name = match self.new_name(path, original_name) {
+                Ok(name) => name,
+                Err(e) => {
+                    self.fail(XorError::io(Operation::Rename, path, e));
+                    return;
+                }
+            };
+            debug!("replaced_name: {:?}", replaced_name);
+
+            // A wrong key, or a crafted name, can decrypt to a name that would move the entry
+            // somewhere else entirely. Encrypting never gives such a name, so unless they're
+            // being quarantined it's taken as a sign of the wrong key.
+            if let Err(reason) = check_name(&replaced_name) {
+                if !self.quarantine {
+                    self.fail(XorError::KeyMismatch {
+                        path : path.to_path_buf(),
+                        reason : format!("its decrypted name {:?} {}", replaced_name, reason)
+                    });
+                    return;
+                }
+
+                let mut quarantined_name = OsString::from(QUARANTINE_PREFIX);
+                quarantined_name.push(original_name);
+                error!("Quarantining '{:?}' as {:?} because its decrypted name {:?} {}", path, quarantined_name, replaced_name, reason);
+                replaced_name = quarantined_name;
+            }
+
+            let parent_path = path.parent().unwrap();
+            let src_file_path = parent_path.join(original_name);
+            let dst_file_path = parent_path.join(&replaced_name);
+
+            // Never replace an existing entry, renaming over it would destroy its data.
+            if self.fs.symlink_metadata(&dst_file_path).is_ok() {
+                let e = io::Error::new(io::ErrorKind::AlreadyExists, format!("{:?} already exists", dst_file_path));
+                self.fail(XorError::io(Operation::Rename, &src_file_path, e));
+                return;
+            }
+
+            let action = if self.mode == Mode::Encrypt && is_placeholder(&replaced_name) {
+                Action::Placeholder { from : src_file_path.clone(), to : dst_file_path.clone() }
+            } else {
+                Action::Rename { from : src_file_path.clone(), to : dst_file_path.clone() }
+            };
+            if self.planned(action) {
+                return;
+            }
+
+            debug!("Moving {:?} to {:?}", src_file_path, dst_file_path);
+
+            let recorded = match self.journal {
+                Some(ref mut journal) => journal.record_rename(&src_file_path, &dst_file_path),
+                None => Ok(())
+            };
+            if let Err(e) = recorded {
+                self.fail(XorError::io(Operation::RecordChange, &src_file_path, e));
+                return;
+            }
+
+            match self.fs.rename(&src_file_path, &dst_file_path) {
+                Ok(_) => {
+                    trace!("Renamed path '{:?}' to '{:?}'", &src_file_path, &dst_file_path);
+                    self.record_done();
+                    self.summary.renamed += 1;
+                    if restoring_placeholder {
+                        self.unrestored_placeholders -= 1;
+                    }
+                },
+                Err(e) => {
+                    self.record_failed();
+                    self.fail(XorError::io(Operation::Rename, &src_file_path, e));
+                }
+            }
+        }
+    }
+
+    /// The name an entry is renamed to.
+    /// Encrypted names longer than the limit are replaced by a placeholder, and placeholders are
+    /// looked up in the manifest when decrypting, see `NameManifest`.
+    fn new_name(&mut self, path : &Path, name : &OsStr) -> io::Result<OsString> {
+        match self.mode {
+            Mode::Encrypt => {
+                let encoded = self.transform_name(name)?;
+                if encoded.len() <= self.max_name_len {
+                    return Ok(encoded);
+                }
+
+                // Encoded names are always ascii.
+                let encoded = encoded.to_string_lossy().into_owned();
+                if self.plan.is_some() {
+                    return Ok(OsString::from(placeholder_for(&encoded)));
+                }
+
+                let (fs, key) = (self.fs, self.key);
+                let placeholder = self.manifest(path)?.add(fs, key, &encoded)?;
+                debug!("Using the placeholder {} for {}", placeholder, encoded);
+                Ok(OsString::from(placeholder))
+            },
+            Mode::Decrypt if is_placeholder(name) => {
+                let placeholder = name.to_string_lossy().into_owned();
+                let encoded = match self.manifest(path)?.lookup(&placeholder) {
+                    Some(encoded) => OsString::from(encoded),
+                    None => return Err(io::Error::new(io::ErrorKind::NotFound, format!("the placeholder {} isn't in the name manifest", placeholder)))
+                };
+                self.transform_name(&encoded)
+            },
+            Mode::Decrypt => self.transform_name(name)
+        }
+    }
+
+    /// The manifest of the tree being processed, which is read the first time it's needed.
+    fn manifest(&mut self, path : &Path) -> io::Result<&mut NameManifest> {
+        if self.manifest.is_none() {
+            let root = match self.root {
+                Some(ref root) => root.clone(),
+                None => path.parent().unwrap_or(path).to_path_buf()
+            };
+            self.manifest = Some(NameManifest::read(self.fs, &root, self.key)?);
+        }
+
+        Ok(self.manifest.as_mut().unwrap())
+    }
+
+    /// Encrypts or decrypts a single name.
+    /// When "mode" is Mode::Encrypt, the bytes of the name are XOR'd then encoded.
+    /// When "mode" is Mode::Decrypt, the name is decoded then XOR'd, giving back the original
+    /// bytes.
+    fn transform_name(&self, name : &OsStr) -> io::Result<OsString> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, format!("{:?} isn't a valid {} name", name, self.name_codec));
+
+        // If in Encrypt mode use the raw bytes of the filename.
+        // If in Decrypt mode decode the filename before getting it's bytes.
+        let mut name_bytes = match self.mode {
+            Mode::Encrypt => name_to_bytes(name)?,
+            Mode::Decrypt => {
+                let encoded = name.to_str().ok_or_else(invalid)?;
+                self.name_codec.decode(encoded).ok_or_else(invalid)?
+            }
+        };
+
+        // Xor encrypt the name, each name starts from the beginning of the key.
+        Keystream::new(self.key).apply(&mut name_bytes);
+
+        // If in Encrypt mode encode the filename.
+        // If in Decrypt mode just use the filename bytes as is.
+        match self.mode {
+            Mode::Encrypt => Ok(OsString::from(self.name_codec.encode(&name_bytes))),
+            Mode::Decrypt => name_from_bytes(name_bytes)
+        }
+    }
+
+    /// Queues every entry in a directory, to be processed under the rules of its ignore file,
+    /// followed by the directory's `Work::Leave`. If the directory or its ignore file can't be
+    /// read nothing is queued, so the directory isn't renamed either. An entry that can't be read
+    /// is a failure, which keeps the directory from being renamed, see `Work::Leave`.
+    fn visit_contents(&mut self, path : &Path, depth : usize, rename : bool) {
+        let failures = self.summary.failures.len();
+        let name = if self.filter.depth() > 0 { Some(self.plain_name(path, true)) } else { None };
+
+        if let Err(e) = self.filter.enter_dir(self.fs, path, name) {
+            self.fail(XorError::io(Operation::ReadIgnoreFile, path, e));
+            return;
+        }
+
+        let mut entries = Vec::new();
+        if depth >= self.max_depth {
+            debug!("Not processing the entries of {:?} which are below the maximum depth", path);
+            self.skipped(path.to_path_buf(), String::from("its entries are below the maximum depth"));
+        } else {
+            let items = match self.fs.read_dir(path) {
+                Ok(items) => items,
+                Err(e) => {
+                    self.filter.leave_dir();
+                    self.fail(XorError::io(Operation::ReadDir, path, e));
+                    return;
+                }
+            };
+
+            for item in items {
+                match item.and_then(|entry| entry.file_type().map(|entry_type| (entry.path(), EntryKind::of(&entry_type)))) {
+                    Ok((path, kind)) => entries.push(Work::Entry { path, kind, depth : depth + 1 }),
+                    Err(e) => self.fail(XorError::io(Operation::ReadDir, path, e))
+                }
+            }
+        }
+
+        // A failure to read an entry stops the walk unless it keeps going.
+        if self.summary.stopped {
+            self.filter.leave_dir();
+            return;
+        }
+
+        self.pending.push(Work::Leave { path : path.to_path_buf(), rename, failures });
+
+        // Queued in reverse so they're taken in the order they were read.
+        self.pending.extend(entries.into_iter().rev());
+    }
+
+    /// How many directories below the root an entry is, the root's own entries being at depth 1.
+    fn depth_of(&self, path : &Path) -> usize {
+        match self.root {
+            Some(ref root) => path.strip_prefix(root).map(|relative| relative.components().count()).unwrap_or(1),
+            None => 1
+        }
+    }
+
+    /// Returns true if "one_file_system" is set and the path is on a different file system to the
+    /// root.
+    fn on_other_file_system(&mut self, path : &Path) -> bool {
+        let identify = match self.one_file_system {
+            Some(identify) => identify,
+            None => return false
+        };
+
+        if self.root_device.is_none() {
+            if let Some(ref root) = self.root {
+                self.root_device = identify(root).ok().map(|(id, _)| id.device);
+            }
+        }
+
+        match (self.root_device, identify(path)) {
+            (Some(root_device), Ok((id, _))) => id.device != root_device,
+            (_, Err(e)) => {
+                info!("Failed to find the file system of {:?} because: {}", path, e);
+                false
+            },
+            _ => false
+        }
+    }
+
+    /// Whether the filter selects an entry, going by its plain name.
+    fn select(&mut self, path : &Path, is_dir : bool) -> Selection {
+        let name = self.plain_name(path, is_dir);
+        self.filter.select(&name, is_dir)
+    }
+
+    /// The name of an entry before it was encrypted, which is what the filter matches against.
+    /// Names that can't be decrypted, or weren't encrypted, are used as they are.
+    fn plain_name(&mut self, path : &Path, is_dir : bool) -> String {
+        let name = match path.file_name() {
+            Some(name) => name,
+            None => return String::new()
+        };
+
+        match self.mode {
+            Mode::Decrypt if self.parts.names(is_dir) => match self.new_name(path, name) {
+                Ok(plain) => plain.to_string_lossy().into_owned(),
+                Err(_) => name.to_string_lossy().into_owned()
+            },
+            _ => name.to_string_lossy().into_owned()
+        }
+    }
+
+    /// XOR's the contents of a file, unless an interrupted run or another hard link to it already
+    /// has. Returns false if the contents couldn't be XOR'd.
+    fn xor_contents(&mut self, path : &Path) -> bool {
+        if !self.parts.contents {
+            return true;
+        }
+
+        let content_done = match self.completed {
+            Some(ref completed) => completed.content_done(path),
+            None => false
+        };
+
+        if content_done || self.linked.contains(path) {
+            return true;
+        }
+
+        if self.planned(Action::Content { path : path.to_path_buf() }) {
+            self.link_other_paths(path);
+            return true;
+        }
+
+        let result = match self.journal {
+            Some(ref mut journal) => rewrite_file_then(self.fs, path, self.key, |temp| journal.record_content(path, temp)),
+            None => rewrite_file(self.fs, path, self.key)
+        };
+
+        if let Err(e) = result {
+            self.record_failed();
+            self.fail(XorError::io(Operation::Xor, path, e));
+            return false;
+        }
+
+        self.record_done();
+        self.summary.contents += 1;
+        self.link_other_paths(path);
+        true
+    }
+
+    /// Replaces the other hard links to a file whose contents were just XOR'd with links to the
+    /// new contents, so they aren't XOR'd again when the walk reaches them.
+    /// None of them have been visited yet, otherwise "path" would have been linked to, so their
+    /// directories haven't been renamed either.
+    fn link_other_paths(&mut self, path : &Path) {
+        let others : Vec<PathBuf> = match self.hard_links {
+            Some(ref hard_links) => hard_links.other_links(path).iter().map(|other| other.to_path_buf()).collect(),
+            None => return
+        };
+
+        for other in others {
+            debug!("Linking {:?} to the XOR'd contents of {:?}", other, path);
+
+            if self.planned(Action::Link { path : other.clone(), existing : path.to_path_buf() }) {
+                self.linked.insert(other);
+                continue;
+            }
+
+            let result = match self.journal {
+                Some(ref mut journal) => link_file_then(self.fs, path, &other, |temp| journal.record_content(&other, temp)),
+                None => link_file(self.fs, path, &other)
+            };
+
+            match result {
+                Ok(_) => {
+                    self.record_done();
+                    self.linked.insert(other);
+                },
+                Err(e) => {
+                    self.record_failed();
+                    self.fail(XorError::io(Operation::Link, &other, e));
+                }
+            }
+        }
+    }
+
+    /// Encrypts or decrypts the names in the target of a symlink, see `SymlinkPolicy::Rewrite`.
+    /// Returns false if the target couldn't be rewritten.
+    fn rewrite_link_target(&mut self, path : &Path) -> bool {
+        let content_done = match self.completed {
+            Some(ref completed) => completed.content_done(path),
+            None => false
+        };
+
+        if content_done {
+            return true;
+        }
+
+        let create_symlink = match self.create_symlink {
+            Some(create_symlink) => create_symlink,
+            None => {
+                let e = io::Error::other("this file system can't create symlinks");
+                self.fail(XorError::io(Operation::RewriteTarget, path, e));
+                return false;
+            }
+        };
+
+        let result = self.fs.read_link(path).and_then(|target| {
+            let rewritten = self.rewrite_target(path, &target)?;
+            debug!("Rewriting the target of {:?} from {:?} to {:?}", path, target, rewritten);
+
+            if self.planned(Action::Target { path : path.to_path_buf(), from : target.clone(), to : rewritten.clone() }) {
+                return Ok(());
+            }
+
+            match self.journal {
+                Some(ref mut journal) => replace_symlink_then(self.fs, create_symlink, &rewritten, path, |temp| journal.record_target(path, temp, &target)),
+                None => replace_symlink(self.fs, create_symlink, &rewritten, path)
+            }
+        });
+
+        if let Err(e) = result {
+            self.record_failed();
+            self.fail(XorError::io(Operation::RewriteTarget, path, e));
+            return false;
+        }
+
+        self.record_done();
+        true
+    }
+
+    /// Encrypts or decrypts each name in a relative symlink target, up to the point the target
+    /// leaves the tree. A name is only transformed if the walk renames the entry it names, so the
+    /// link still resolves when the entry is excluded, ignored or outside the depth limits.
+    fn rewrite_target(&mut self, link : &Path, target : &Path) -> io::Result<PathBuf> {
+        if target.is_absolute() {
+            return Ok(target.to_path_buf());
+        }
+
+        // How many directories the link is below the root, so a target climbing out of the tree
+        // can be spotted.
+        let mut depth = match (link.parent(), &self.root) {
+            (Some(parent), Some(root)) => parent.strip_prefix(root).map(|p| p.components().count()).unwrap_or(0),
+            _ => 0
+        };
+        // The filter as it stands in the link's directory, moved along the target as it goes.
+        let mut filter = self.filter.clone();
+        let mut dir = link.parent().map(Path::to_path_buf).unwrap_or_default();
+        let mut transforming = true;
+        let mut rewritten = PathBuf::new();
+        let components : Vec<Component> = target.components().collect();
+
+        for (index, &component) in components.iter().enumerate() {
+            match component {
+                Component::ParentDir if transforming => {
+                    if depth == 0 {
+                        transforming = false;
+                    } else {
+                        depth -= 1;
+                        filter.leave_dir();
+                        dir.pop();
+                    }
+                    rewritten.push(component.as_os_str());
+                },
+                Component::Normal(name) if transforming => {
+                    let is_dir = index + 1 < components.len() || self.target_is_dir(link, &rewritten, name);
+                    let transformed = if self.parts.names(is_dir) { self.new_name(link, name) } else { Ok(name.to_os_string()) };
+
+                    // Names that can't be decrypted weren't encrypted, as with `plain_name`.
+                    let plain = match (self.mode, &transformed) {
+                        (Mode::Decrypt, Ok(plain)) => plain.to_string_lossy().into_owned(),
+                        _ => name.to_string_lossy().into_owned()
+                    };
+
+                    // The entry may or may not have been renamed by the walk yet.
+                    let path = match transformed {
+                        Ok(ref transformed) if self.fs.symlink_metadata(dir.join(name)).is_err() => dir.join(transformed),
+                        _ => dir.join(name)
+                    };
+
+                    depth += 1;
+                    match self.target_name(&filter, &path, &plain, is_dir, depth) {
+                        TargetName::Renamed => {
+                            let name = transformed?;
+                            if let Err(reason) = check_name(&name) {
+                                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("the target name {:?} {}", name, reason)));
+                            }
+                            rewritten.push(name);
+                        },
+                        TargetName::Kept => rewritten.push(name),
+                        TargetName::Untouched => {
+                            rewritten.push(name);
+                            transforming = false;
+                        }
+                    }
+
+                    if transforming && index + 1 < components.len() {
+                        filter.enter_dir(self.fs, &path, Some(plain))?;
+                        dir = path;
+                    }
+                },
+                _ => rewritten.push(component.as_os_str())
+            }
+        }
+
+        Ok(rewritten)
+    }
+
+    /// What the walk does with the entry at "path", "depth" directories below the root, named in a
+    /// symlink target. "filter" is in the entry's directory and "plain" is its plain name. This
+    /// follows `visit_entry` and `visit_contents`.
+    fn target_name(&mut self, filter : &Filter, path : &Path, plain : &str, is_dir : bool, depth : usize) -> TargetName {
+        if depth > self.max_depth || is_reserved_file(Path::new(plain), depth == 1) || is_temp_file(Path::new(plain)) {
+            return TargetName::Untouched;
+        }
+
+        if is_dir && self.on_other_file_system(path) {
+            return TargetName::Untouched;
+        }
+
+        match filter.select(plain, is_dir) {
+            Selection::Selected if depth >= self.min_depth => TargetName::Renamed,
+            Selection::Excluded(_) => TargetName::Untouched,
+            _ => TargetName::Kept
+        }
+    }
+
+    /// Whether the last name in a symlink target is a directory, when only one of file and
+    /// directory names are transformed. This goes by whatever the link points at, or else by the
+    /// entry the name is renamed to, since the walk may have got to it first. Targets that don't
+    /// exist either way count as files.
+    fn target_is_dir(&mut self, link : &Path, parent : &Path, name : &OsStr) -> bool {
+        if self.parts.file_names == self.parts.dir_names {
+            return false;
+        }
+
+        if let Ok(metadata) = self.fs.metadata(link) {
+            return metadata.is_dir();
+        }
+
+        let dir = link.parent().unwrap_or(link).join(parent);
+        match self.new_name(link, name) {
+            Ok(renamed) => self.fs.metadata(dir.join(renamed)).map(|metadata| metadata.is_dir()).unwrap_or(false),
+            Err(_) => false
+        }
+    }
+
+    /// Processes whatever a symlink points at, see `SymlinkPolicy::Follow`.
+    /// Returns true if the symlink points at a directory whose contents were queued, in which case
+    /// the symlink is renamed once they're finished.
+    fn follow_link(&mut self, path : &Path, depth : usize) -> bool {
+        let target = match self.fs.canonicalize(path) {
+            Ok(target) => target,
+            Err(e) => {
+                debug!("Not following {:?} because: {}", path, e);
+                return false;
+            }
+        };
+
+        if self.on_other_file_system(&target) {
+            debug!("Not following {:?} to {:?} which is on another file system", path, target);
+            return false;
+        }
+
+        if !self.visited.insert(target.clone()) {
+            debug!("Not following {:?} to {:?} which was already processed", path, target);
+            return false;
+        }
+
+        match self.fs.metadata(&target) {
+            Ok(ref metadata) if metadata.is_dir() => {
+                self.pending.push(Work::Contents { path : path.to_path_buf(), depth, rename : true });
+                return true;
+            },
+            Ok(ref metadata) if metadata.is_file() => { self.xor_contents(&target); },
+            Ok(_) => (),
+            Err(e) => self.fail(XorError::io(Operation::Follow, path, e))
+        }
+
+        false
+    }
+
+    /// Returns false if symlinks are being followed and the file or directory at "path" was
+    /// already reached through one, or true the first time it's reached.
+    fn first_visit(&mut self, path : &Path) -> bool {
+        if self.symlinks != SymlinkPolicy::Follow {
+            return true;
+        }
+
+        match self.fs.canonicalize(path) {
+            Ok(canonical) => self.visited.insert(canonical),
+            Err(_) => true
+        }
+    }
+
+    /// Updates the marker to match the mode then removes the journal. Nothing is updated if
+    /// anything failed, the journal is left so the run can be resumed or rolled back.
+    fn finish(self, root : &Path) -> Result<Summary, XorError> {
+        if !self.summary.succeeded() {
+            return Ok(self.summary);
+        }
+
+        let finish_error = |e| XorError::io(Operation::Finish, root, e);
+        match self.mode {
+            Mode::Encrypt => write_marker(self.fs, root, self.name_codec, self.parts).map_err(finish_error)?,
+            Mode::Decrypt => remove_marker(self.fs, root).map_err(finish_error)?
+        }
+
+        // The manifest is kept while any placeholder is left, otherwise its name would be lost.
+        if self.mode == Mode::Decrypt && self.unrestored_placeholders == 0 {
+            remove_manifest(self.fs, root).map_err(finish_error)?;
+        }
+
+        remove_journal(self.fs, root).map_err(finish_error)?;
+        Ok(self.summary)
+    }
+
+    /// Returns true if an interrupted run already renamed an entry to "path".
+    fn already_renamed(&self, path : &Path) -> bool {
+        match self.completed {
+            Some(ref completed) => completed.renamed_to(path),
+            None => false
+        }
+    }
+
+    /// Adds an action to the plan when only planning, see `plan`. Returns true if so, in which
+    /// case the change mustn't be made.
+    fn planned(&mut self, action : Action) -> bool {
+        match self.plan {
+            Some(ref mut plan) => {
+                plan.actions.push(action);
+                true
+            },
+            None => false
+        }
+    }
+
+    /// Logs a failure and adds it to the plan or the summary. Unless the walker keeps going, the
+    /// rest of the walk is abandoned.
+    fn fail(&mut self, error : XorError) {
+        error!("{}", error);
+
+        let reason = match error {
+            XorError::Io { error : ref e, .. } => e.to_string(),
+            XorError::KeyMismatch { ref reason, .. } => reason.clone()
+        };
+        if self.planned(Action::Fail { path : error.path().to_path_buf(), reason }) {
+            return;
+        }
+
+        self.summary.failures.push(error);
+        if !self.keep_going {
+            self.summary.stopped = true;
+            self.pending.clear();
+        }
+    }
+
+    /// Counts an entry that's left alone on purpose and adds it to the plan.
+    fn skipped(&mut self, path : PathBuf, reason : String) {
+        self.summary.skipped += 1;
+        self.planned(Action::Skip { path, reason });
+    }
+
+    fn record_done(&mut self) {
+        if let Some(ref mut journal) = self.journal {
+            if let Err(e) = journal.record_done() {
+                error!("Failed to record a finished change in the journal because: {}", e);
+            }
+        }
+    }
+
+    fn record_failed(&mut self) {
+        if let Some(ref mut journal) = self.journal {
+            if let Err(e) = journal.record_failed() {
+                error!("Failed to record a failure in the journal because: {}", e);
+            }
+        }
+    }
+}
+
+/// Encrypts or decrypts everything below the given directory, the directory itself isn't renamed.
+/// See `Walker::run` for how the tree is protected from being XOR'd twice.
+/// The first failure is returned as an error, see `Walker::run` for all of them. An empty key is
+/// an `InvalidInput` error.
+pub fn encrypt_path<T: GenFS>(fs: &T, p : &Path, key : &[u8], mode : &Mode) -> io::Result<()> {
+    check_key(key)?;
+    Walker::new(fs, key, *mode).run(p)?.into_result()
+}
+
+/// Finishes a run that was interrupted, skipping the changes recorded in its journal.
+/// The run continues in the mode it was started in. An empty key is an `InvalidInput` error.
+pub fn resume_path<T: GenFS>(fs: &T, p : &Path, key : &[u8]) -> io::Result<()> {
+    check_key(key)?;
+    Walker::new(fs, key, Mode::Encrypt).resume(p)?.into_result()
+}
+
+/// Undoes the changes made by a run that was interrupted, using its journal.
+/// Symlink targets that were rewritten can't be restored, use `Walker::roll_back` for those.
+/// An empty key is an `InvalidInput` error.
+pub fn rollback_path<T: GenFS>(fs: &T, p : &Path, key : &[u8]) -> io::Result<()> {
+    check_key(key)?;
+    Ok(Walker::new(fs, key, Mode::Encrypt).roll_back(p)?)
+}
+
+/// Encrypts or decrypts a single directory entry according to its type.
+///
+/// # Panics
+///
+/// If the key is empty, see `keystream::check_key`.
+pub fn xor_entry<T: rsfs::DirEntry, U: GenFS>(fs: &U, entry : &T, key : &[u8], mode : &Mode) {
+    Walker::new(fs, key, *mode).xor_entry(entry);
+}
+
+/// XOR's the contents of a file in place then renames it.
+///
+/// # Panics
+///
+/// If the key is empty, see `keystream::check_key`.
+pub fn xor_file<T, P>(fs: &T, path : &P, key : &[u8], mode : &Mode)
+    where T: GenFS, P: AsRef<Path> + Debug {
+        Walker::new(fs, key, *mode).xor_file(path.as_ref());
+    }
+
+/// Renames a symlink, the link target is left untouched.
+/// See `Walker::symlinks` for the other ways of handling symlinks.
+///
+/// # Panics
+///
+/// If the key is empty, see `keystream::check_key`.
+pub fn xor_symlink<T, P>(fs: &T, entry : &P, key : &[u8], mode : &Mode)
+    where T: GenFS, P: AsRef<Path> + Debug {
+        Walker::new(fs, key, *mode).xor_symlink(entry.as_ref());
+    }
+
+/// Recursively encrypts or decrypts the contents of a directory then renames the directory.
+///
+/// # Panics
+///
+/// If the key is empty, see `keystream::check_key`.
+pub fn xor_dir<T, P>(fs: &T, path : &P, key : &[u8], mode : &Mode)
+    where T: GenFS, P: AsRef<Path> + Debug {
+        Walker::new(fs, key, *mode).xor_dir(path.as_ref());
+    }
+
+/// Renames a directory entry, see `Walker::rename_entry`.
+///
+/// # Panics
+///
+/// If the key is empty, see `keystream::check_key`.
+pub fn rename_entry<T, P>(fs: &T, path : P, key : &[u8], mode : &Mode)
+    where T: GenFS, P: AsRef<Path> + Debug {
+        Walker::new(fs, key, *mode).rename_entry(path.as_ref());
+    }
+
+/// Returns true for the files xor keeps at the root of a tree, when "at_root" is set, and for
+/// ignore files anywhere, which are never encrypted. Below the root those names are ordinary files.
+pub fn is_reserved_file(path : &Path, at_root : bool) -> bool {
+    (at_root && (is_journal_file(path) || is_marker_file(path) || is_manifest_file(path))) || is_ignore_file(path)
+}
+
+/// Checks that a name can be joined onto its parent directory without naming some other place:
+/// it mustn't be empty, "." or "..", or contain a path separator or a NUL byte.
+/// Returns the reason the name is unsafe otherwise.
+pub fn check_name(name : &OsStr) -> Result<(), &'static str> {
+    let bytes = match name_to_bytes(name) {
+        Ok(bytes) => bytes,
+        Err(_) => return Err("isn't valid unicode")
+    };
+
+    if bytes.is_empty() {
+        Err("is empty")
+    } else if bytes == b"." || bytes == b".." {
+        Err("refers to a directory")
+    } else if bytes.contains(&0) {
+        Err("contains a NUL byte")
+    } else if bytes.iter().any(|&b| b < 0x80 && is_separator(b as char)) {
+        Err("contains a path separator")
+    } else {
+        Ok(())
+    }
+}
+
+/// The raw bytes of a file name, so names that aren't valid unicode can be encrypted too.
+#[cfg(unix)]
+fn name_to_bytes(name : &OsStr) -> io::Result<Vec<u8>> {
+    use std::os::unix::ffi::OsStrExt;
+    Ok(name.as_bytes().to_vec())
+}
+
+/// The file name made of exactly the given bytes.
+#[cfg(unix)]
+fn name_from_bytes(bytes : Vec<u8>) -> io::Result<OsString> {
+    use std::os::unix::ffi::OsStringExt;
+    Ok(OsString::from_vec(bytes))
+}
+
+/// Other platforms don't expose the bytes of a file name, so only unicode names are supported.
+#[cfg(not(unix))]
+fn name_to_bytes(name : &OsStr) -> io::Result<Vec<u8>> {
+    match name.to_str() {
+        Some(name) => Ok(name.as_bytes().to_vec()),
+        None => Err(io::Error::new(io::ErrorKind::InvalidData, format!("{:?} isn't valid unicode", name)))
+    }
+}
+
+#[cfg(not(unix))]
+fn name_from_bytes(bytes : Vec<u8>) -> io::Result<OsString> {
+    String::from_utf8(bytes)
+        .map(OsString::from)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "the name doesn't decrypt to valid unicode"))
+}
+
+/// Encodes bytes as an uppercase hex string.
+pub fn to_hex_string(bytes: &[u8]) -> String {
+    let strings: Vec<String> = bytes
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect();
+
+    strings.join("")
+}
+
+/// Decodes a hex string, either case is accepted.
+pub fn from_hex_string(hex : &str) -> Result<Vec<u8>, FromHexError> {
+    FromHex::from_hex(hex)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rsfs::mem::FS;
+    use atomic_file::FILE_BLOCK_SIZE;
+    use std::io::{Write, Read};
+    use std::path::PathBuf;
+    use std::thread;
+
+    fn read_file_contents<T: GenFS, P: AsRef<Path>>(fs: &T, path: P) -> Vec<u8> {
+        let mut x = fs.open_file(path).unwrap();
+        let mut data : Vec<u8> = Vec::new();
+
+        let _num_read = x.read_to_end(&mut data).unwrap();
+
+        data
+    }
+
+    #[test]
+    fn to_hex_string_works() {
+        let input_string = String::from("hello");
+        let input_bytes = input_string.into_bytes();
+
+        let hex_string = to_hex_string(&input_bytes);
+        assert_eq!(hex_string, "68656C6C6F");
+    }
+
+    #[test]
+    fn from_hex_string_works() {
+        let input_string = String::from("68656C6C6F");
+        let ascii_bytes = from_hex_string(&input_string).unwrap();
+        let expected_bytes = vec![104, 101, 108, 108, 111];
+
+        assert_eq!(expected_bytes, ascii_bytes);
+    }
+
+    #[test]
+    fn xor_file_encrypt_mode_works() {
+        // Arrange.
+
+        // Setup the input file
+        let fs = FS::new();
+        let input_data = "hello world".as_bytes();
+        let input_path = Path::new("/input.txt");
+        let key = vec![71];
+        let mode = Mode::Encrypt;
+        let mut input_file = fs.create_file(input_path).unwrap();
+        input_file.write_all(input_data).unwrap();
+
+        // Act.
+        xor_file(&fs, &input_path, &key, &mode);
+
+        // Assert.
+        let filenames : Vec<String> = fs.read_dir("/")
+            .unwrap()
+            .map(|x| x.unwrap())
+            .map(|x| x.path().into_os_string())
+            .map(|x| x.into_string().unwrap())
+            .collect();
+
+        let mut encrypted_file = fs.open_file("/2E2937323369333F33").unwrap();
+        let mut encrypted_bytes : Vec<u8> = Vec::new();
+        encrypted_file.read_to_end(&mut encrypted_bytes).unwrap();
+
+        // Filename is XOR'd against the key then encoded to hex
+        assert_eq!(filenames, vec!["/2E2937323369333F33"]);
+        assert_eq!(encrypted_bytes, vec![0x2f_u8, 0x22_u8, 0x2b_u8, 0x2b_u8, 0x28_u8, 0x67_u8, 0x30_u8, 0x28_u8, 0x35_u8, 0x2b_u8, 0x23_u8]);
+    }
+
+    #[test]
+    fn xor_file_decrypt_mode_works() {
+        // Arrange.
+
+        // Setup the input file
+        let fs = FS::new();
+        let input_path = Path::new("/2E2937323369333F33");
+        let input_data = [0x2f_u8, 0x22_u8, 0x2b_u8, 0x2b_u8, 0x28_u8, 0x67_u8, 0x30_u8, 0x28_u8, 0x35_u8, 0x2b_u8, 0x23_u8];
+        let mut input_file = fs.create_file(input_path).unwrap();
+        input_file.write_all(&input_data).unwrap();
+
+        let key = vec![71];
+        //let mode = Mode::Decrypt;
+
+        // Act.
+        xor_file(&fs, &input_path, &key, &Mode::Decrypt);
+
+        // Assert.
+        let mut output_file = fs.open_file("/input.txt").unwrap();
+        let mut encrypted_bytes : Vec<u8> = Vec::new();
+        output_file.read_to_end(&mut encrypted_bytes).unwrap();
+
+        // Filename is XOR'd against the key then encoded to hex
+        assert_eq!(encrypted_bytes, "hello world".as_bytes());
+    }
+
+    #[test]
+    fn xor_file_spanning_several_blocks_works() {
+        let fs = FS::new();
+        let key : Vec<u8> = (1..=200).collect();
+        let contents : Vec<u8> = (0..(FILE_BLOCK_SIZE * 2 + 100)).map(|i| (i % 256) as u8).collect();
+        fs.create_file("/big").unwrap().write_all(&contents).unwrap();
+
+        // "big" XOR'd against the first key bytes 1, 2, 3 is "ckd".
+        xor_file(&fs, &Path::new("/big"), &key, &Mode::Encrypt);
+
+        let mut expected = contents.clone();
+        Keystream::new(&key).apply(&mut expected);
+        assert_eq!(read_file_contents(&fs, "/636B64"), expected);
+
+        xor_file(&fs, &Path::new("/636B64"), &key, &Mode::Decrypt);
+        assert_eq!(read_file_contents(&fs, "/big"), contents);
+    }
+
+    #[test]
+    fn leftover_temporary_files_are_left_alone_by_a_walk() {
+        let fs = FS::new();
+        fs.create_dir("/root").unwrap();
+        fs.create_file("/root/.xor-tmp.1234.0").unwrap().write_all(b"partial").unwrap();
+        fs.create_file("/root/a").unwrap().write_all(b"a").unwrap();
+
+        encrypt_path(&fs, Path::new("/root"), &[71], &Mode::Encrypt).unwrap();
+
+        let mut names : Vec<PathBuf> = fs.read_dir("/root").unwrap().map(|e| e.unwrap().path()).collect();
+        names.sort();
+        assert_eq!(names, vec![PathBuf::from("/root/.xor-encrypted"), PathBuf::from("/root/.xor-tmp.1234.0"), PathBuf::from("/root/26")]);
+        assert_eq!(read_file_contents(&fs, "/root/.xor-tmp.1234.0"), b"partial");
+    }
+
+    #[test]
+    fn reserved_names_below_the_root_are_encrypted() {
+        let fs = FS::new();
+        let root = Path::new("/root");
+        fs.create_dir_all("/root/dir").unwrap();
+        fs.create_file("/root/dir/.xor-names").unwrap().write_all(b"names").unwrap();
+        fs.create_file("/root/dir/.xor-journal").unwrap().write_all(b"journal").unwrap();
+
+        encrypt_path(&fs, root, &[71], &Mode::Encrypt).unwrap();
+        let names : Vec<PathBuf> = fs.read_dir("/root/232E35").unwrap().map(|e| e.unwrap().path()).collect();
+        assert_eq!(names.len(), 2);
+        assert!(names.iter().all(|name| !name.to_string_lossy().contains(".xor-")));
+
+        encrypt_path(&fs, root, &[71], &Mode::Decrypt).unwrap();
+        assert_eq!(read_file_contents(&fs, "/root/dir/.xor-names"), b"names");
+        assert_eq!(read_file_contents(&fs, "/root/dir/.xor-journal"), b"journal");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn names_that_are_not_unicode_are_restored_byte_for_byte() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        // "fé" in Latin-1.
+        let name = OsStr::from_bytes(&[0x66, 0xE9]);
+        let fs = FS::new();
+        fs.create_file(Path::new("/").join(name)).unwrap().write_all(b"latin").unwrap();
+
+        rename_entry(&fs, Path::new("/").join(name), &[71], &Mode::Encrypt);
+        assert_eq!(read_file_contents(&fs, "/21AE"), b"latin");
+
+        rename_entry(&fs, Path::new("/21AE"), &[71], &Mode::Decrypt);
+        assert_eq!(read_file_contents(&fs, Path::new("/").join(name)), b"latin");
+    }
+
+    #[test]
+    fn unsafe_decrypted_names_fail_or_are_quarantined() {
+        let fs = FS::new();
+        let root = Path::new("/root");
+        fs.create_dir_all(root).unwrap();
+
+        // These decrypt to "../x", "..", "a\0" and "ok".
+        for name in &["6969683F", "6969", "2647", "282C"] {
+            fs.create_file(root.join(name)).unwrap();
+        }
+
+        let summary = Walker::new(&fs, &[71], Mode::Decrypt).force(true).keep_going(true).run(root).unwrap();
+        assert_eq!(summary.failures.len(), 3);
+        assert!(summary.failures.iter().all(|failure| failure.is_key_mismatch()));
+
+        let mut names : Vec<PathBuf> = fs.read_dir(root).unwrap().map(|e| e.unwrap().path()).collect();
+        names.sort();
+        assert_eq!(names, vec![root.join(".xor-journal"), root.join("2647"), root.join("6969"), root.join("6969683F"), root.join("ok")]);
+
+        // Resuming with quarantine moves the unsafe names aside and finishes the run.
+        let summary = Walker::new(&fs, &[71], Mode::Decrypt).quarantine(true).resume(root).unwrap();
+        assert!(summary.succeeded());
+        assert!(fs.metadata("/root/ok").unwrap().is_file());
+
+        assert!(fs.metadata("/root/quarantined-6969683F").unwrap().is_file());
+        assert!(fs.metadata("/root/quarantined-6969").unwrap().is_file());
+        assert!(fs.metadata("/root/quarantined-2647").unwrap().is_file());
+    }
+
+    #[test]
+    fn decrypting_uses_the_name_encoding_recorded_in_the_marker() {
+        let fs = FS::new();
+        let root = Path::new("/root");
+        fs.create_dir_all("/root/dir").unwrap();
+        fs.create_file("/root/dir/file").unwrap().write_all(b"data").unwrap();
+
+        Walker::new(&fs, &[71], Mode::Encrypt).name_codec(NameCodec::Base32).run(root).unwrap();
+
+        // "dir" XOR'd is 0x23 0x2E 0x35 and "file" is 0x21 0x2E 0x2B 0x22.
+        assert!(fs.metadata("/root/EMXDK/EEXCWIQ").unwrap().is_file());
+
+        Walker::new(&fs, &[71], Mode::Decrypt).run(root).unwrap();
+        assert_eq!(read_file_contents(&fs, "/root/dir/file"), b"data");
+    }
+
+    #[test]
+    fn names_that_are_too_long_are_replaced_by_placeholders() {
+        let fs = FS::new();
+        let root = Path::new("/root");
+        fs.create_dir_all("/root/a_long_directory_name").unwrap();
+        fs.create_file("/root/a_long_directory_name/short").unwrap().write_all(b"data").unwrap();
+
+        Walker::new(&fs, &[71], Mode::Encrypt).max_name_len(20).run(root).unwrap();
+
+        let names : Vec<String> = fs.read_dir(root).unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .filter(|name| !name.starts_with('.'))
+            .collect();
+        assert_eq!(names.len(), 1);
+        assert!(names[0].starts_with("~"));
+        assert!(fs.metadata(root.join(&names[0]).join("342F283533")).unwrap().is_file());
+
+        Walker::new(&fs, &[71], Mode::Decrypt).run(root).unwrap();
+
+        assert_eq!(read_file_contents(&fs, "/root/a_long_directory_name/short"), b"data");
+        assert!(fs.metadata("/root/.xor-names").is_err());
+    }
+
+    #[test]
+    fn planning_changes_nothing() {
+        let fs = FS::new();
+        let root = Path::new("/root");
+        fs.create_dir_all("/root/dir").unwrap();
+        fs.create_file("/root/dir/file").unwrap().write_all(b"data").unwrap();
+
+        let plan = Walker::new(&fs, &[71], Mode::Encrypt).plan(root).unwrap();
+
+        assert_eq!(plan.actions, vec![
+            Action::Content { path : PathBuf::from("/root/dir/file") },
+            Action::Rename { from : PathBuf::from("/root/dir/file"), to : PathBuf::from("/root/dir/212E2B22") },
+            Action::Rename { from : PathBuf::from("/root/dir"), to : PathBuf::from("/root/232E35") }
+        ]);
+        assert_eq!(read_file_contents(&fs, "/root/dir/file"), b"data");
+
+        let names : Vec<PathBuf> = fs.read_dir(root).unwrap().map(|e| e.unwrap().path()).collect();
+        assert_eq!(names, vec![PathBuf::from("/root/dir")]);
+    }
+
+    #[test]
+    fn filters_match_plain_names_in_both_directions() {
+        let fs = FS::new();
+        let root = Path::new("/root");
+        fs.create_dir_all("/root/dir").unwrap();
+        fs.create_file("/root/.xorignore").unwrap().write_all(b"*.log\n").unwrap();
+        fs.create_file("/root/dir/a.txt").unwrap().write_all(b"data").unwrap();
+        fs.create_file("/root/dir/b.log").unwrap().write_all(b"log").unwrap();
+
+        Walker::new(&fs, &[71], Mode::Encrypt).run(root).unwrap();
+
+        assert_eq!(read_file_contents(&fs, "/root/232E35/2669333F33"), b"#&3&");
+        assert_eq!(read_file_contents(&fs, "/root/232E35/b.log"), b"log");
+        assert_eq!(read_file_contents(&fs, "/root/.xorignore"), b"*.log\n");
+
+        // Only the file is included, so its directory keeps the encrypted name.
+        Walker::new(&fs, &[71], Mode::Decrypt).include("dir/a.txt".parse().unwrap()).run(root).unwrap();
+
+        assert_eq!(read_file_contents(&fs, "/root/232E35/a.txt"), b"data");
+        assert_eq!(read_file_contents(&fs, "/root/232E35/b.log"), b"log");
+    }
+
+    #[test]
+    fn depth_limits_leave_the_shallowest_and_deepest_entries_alone() {
+        let fs = FS::new();
+        fs.create_dir_all("/root/a/b/c").unwrap();
+        fs.create_file("/root/top").unwrap().write_all(b"top").unwrap();
+        fs.create_file("/root/a/b/c/file").unwrap().write_all(b"deep").unwrap();
+
+        Walker::new(&fs, &[71], Mode::Encrypt).min_depth(2).max_depth(3).run(Path::new("/root")).unwrap();
+
+        assert_eq!(read_file_contents(&fs, "/root/top"), b"top");
+        assert_eq!(read_file_contents(&fs, "/root/a/25/24/file"), b"deep");
+    }
+
+    #[test]
+    fn very_deep_trees_do_not_overflow_the_stack() {
+        let fs = FS::new();
+        let mut path = PathBuf::from("/root");
+        for _ in 0..1000 {
+            path.push("d");
+        }
+        fs.create_dir_all(&path).unwrap();
+        fs.create_file(path.join("file")).unwrap().write_all(b"data").unwrap();
+
+        // The stack is far too small to recurse once per directory.
+        let walk = thread::Builder::new().stack_size(128 * 1024).spawn(move || {
+            Walker::new(&fs, &[71], Mode::Encrypt).run(Path::new("/root")).map(|_| fs)
+        }).unwrap();
+        let fs = walk.join().unwrap().unwrap();
+
+        let mut path = PathBuf::from("/root");
+        for _ in 0..1000 {
+            path.push("23");
+        }
+        assert_eq!(read_file_contents(&fs, path.join("212E2B22")), b"#&3&");
+    }
+
+    #[test]
+    fn decrypting_transforms_the_parts_recorded_in_the_marker() {
+        let fs = FS::new();
+        let root = Path::new("/root");
+        fs.create_dir_all("/root/dir").unwrap();
+        fs.create_file("/root/dir/file").unwrap().write_all(b"data").unwrap();
+
+        let parts = Parts { contents : false, file_names : true, dir_names : false };
+        Walker::new(&fs, &[71], Mode::Encrypt).parts(parts).run(root).unwrap();
+
+        assert_eq!(read_file_contents(&fs, "/root/dir/212E2B22"), b"data");
+        assert_eq!("file-names".parse::<Parts>().unwrap(), parts);
+
+        Walker::new(&fs, &[71], Mode::Decrypt).run(root).unwrap();
+
+        assert_eq!(read_file_contents(&fs, "/root/dir/file"), b"data");
+    }
+
+    #[test]
+    fn failures_stop_the_run_unless_the_walker_keeps_going() {
+        let fs = FS::new();
+        let root = Path::new("/root");
+        fs.create_dir_all(root).unwrap();
+        // "x" encrypts to "3F", which is excluded so it's still in the way.
+        for name in &["3F", "a", "b", "x"] {
+            fs.create_file(root.join(name)).unwrap();
+        }
+
+        let walker = || Walker::new(&fs, &[71], Mode::Encrypt).parts(Parts { contents : false, ..Parts::all() }).exclude("3F".parse().unwrap());
+
+        let summary = walker().run(root).unwrap();
+        assert!(summary.stopped);
+        assert_eq!(summary.failures.len(), 1);
+        assert_eq!(summary.failures[0].path(), root.join("x"));
+        assert!(read_journal(&fs, root).unwrap().is_some());
+        assert!(read_marker(&fs, root).unwrap().is_none());
+
+        roll_back(&fs, root, &[71], None).unwrap();
+
+        let summary = walker().keep_going(true).run(root).unwrap();
+        assert!(!summary.stopped);
+        assert_eq!((summary.renamed, summary.skipped, summary.failures.len()), (2, 1, 1));
+        assert!(fs.metadata("/root/26").is_ok() && fs.metadata("/root/25").is_ok());
+        assert!(fs.metadata("/root/x").is_ok());
+    }
+
+    #[test]
+    fn directories_with_failures_are_finished_when_resumed() {
+        let fs = FS::new();
+        let root = Path::new("/root");
+        fs.create_dir_all("/root/d").unwrap();
+        // "x" encrypts to "3F", which is excluded so it's still in the way.
+        for name in &["3F", "a", "x"] {
+            fs.create_file(Path::new("/root/d").join(name)).unwrap();
+        }
+
+        let walker = || Walker::new(&fs, &[71], Mode::Encrypt).parts(Parts { contents : false, ..Parts::all() }).exclude("3F".parse().unwrap());
+
+        let summary = walker().keep_going(true).run(root).unwrap();
+        assert_eq!(summary.failures.len(), 1);
+        assert!(fs.metadata("/root/d/26").is_ok());
+        assert!(fs.metadata("/root/23").is_err());
+
+        fs.remove_file("/root/d/3F").unwrap();
+
+        let summary = walker().resume(root).unwrap();
+        assert!(summary.succeeded());
+        assert!(fs.metadata("/root/23/26").is_ok() && fs.metadata("/root/23/3F").is_ok());
+        assert!(read_journal(&fs, root).unwrap().is_none());
+        assert!(read_marker(&fs, root).unwrap().is_some());
+    }
+
+    #[test]
+    fn decrypting_with_a_different_key_is_caught() {
+        let fs = FS::new();
+        let root = Path::new("/root");
+        fs.create_dir_all(root).unwrap();
+        fs.create_file("/root/a").unwrap().write_all(b"data").unwrap();
+
+        Walker::new(&fs, &[71], Mode::Encrypt).run(root).unwrap();
+
+        // "26" decrypts to "/" with the key 9, which encrypting never gives.
+        let summary = Walker::new(&fs, &[9], Mode::Decrypt).run(root).unwrap();
+        assert_eq!(summary.failures.len(), 1);
+        assert!(summary.failures[0].is_key_mismatch());
+
+        Walker::new(&fs, &[9], Mode::Decrypt).roll_back(root).unwrap();
+        assert_eq!(read_file_contents(&fs, "/root/26"), b"#&3&");
+
+        Walker::new(&fs, &[71], Mode::Decrypt).run(root).unwrap();
+        assert_eq!(read_file_contents(&fs, "/root/a"), b"data");
+
+        // The manifest won't parse with the wrong key, so nothing is changed at all.
+        fs.create_file("/root/a-name-too-long-to-keep").unwrap();
+        Walker::new(&fs, &[71], Mode::Encrypt).max_name_len(20).run(root).unwrap();
+
+        let err = Walker::new(&fs, &[72], Mode::Decrypt).run(root).unwrap_err();
+        assert!(err.is_key_mismatch());
+        assert_eq!(read_file_contents(&fs, "/root/26"), b"#&3&");
+    }
+
+    #[test]
+    fn rename_entry_never_replaces_an_existing_entry() {
+        let fs = FS::new();
+        fs.create_file("/a").unwrap().write_all(b"plain").unwrap();
+        fs.create_file("/26").unwrap().write_all(b"other").unwrap();
+
+        rename_entry(&fs, Path::new("/a"), &[71], &Mode::Encrypt);
+
+        assert_eq!(read_file_contents(&fs, "/a"), b"plain");
+        assert_eq!(read_file_contents(&fs, "/26"), b"other");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rewritten_symlinks_resolve_after_encrypting_and_decrypting() {
+        use rsfs::unix_ext::GenFSExt;
+
+        let fs = FS::new();
+        let root = Path::new("/root");
+        fs.create_dir_all("/root/dir").unwrap();
+        fs.create_file("/root/dir/file").unwrap().write_all(b"data").unwrap();
+        fs.symlink("dir/file", "/root/link").unwrap();
+        fs.symlink("../outside", "/root/up").unwrap();
+
+        let walker = |mode| Walker::new(&fs, &[71], mode)
+            .symlinks(SymlinkPolicy::Rewrite)
+            .creating_symlinks_with(create_symlink);
+
+        walker(Mode::Encrypt).run(root).unwrap();
+
+        // "link" is "2B2E292C", "dir/file" is "232E35/212E2B22" and "up" is "3237".
+        assert_eq!(fs.read_link("/root/2B2E292C").unwrap(), PathBuf::from("232E35/212E2B22"));
+        assert_eq!(fs.read_link("/root/3237").unwrap(), PathBuf::from("../outside"));
+        assert_eq!(read_file_contents(&fs, "/root/2B2E292C"), b"#&3&");
+
+        walker(Mode::Decrypt).run(root).unwrap();
+
+        assert_eq!(fs.read_link("/root/link").unwrap(), PathBuf::from("dir/file"));
+        assert_eq!(fs.read_link("/root/up").unwrap(), PathBuf::from("../outside"));
+        assert_eq!(read_file_contents(&fs, "/root/link"), b"data");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rewritten_symlinks_to_entries_the_walk_leaves_alone_still_resolve() {
+        use rsfs::unix_ext::GenFSExt;
+
+        let fs = FS::new();
+        let root = Path::new("/root");
+        fs.create_dir_all("/root/dir").unwrap();
+        fs.create_dir_all("/root/skip").unwrap();
+        fs.create_file("/root/dir/file").unwrap().write_all(b"data").unwrap();
+        fs.create_file("/root/dir/secret").unwrap().write_all(b"data").unwrap();
+        fs.create_file("/root/dir/.xorignore").unwrap().write_all(b"secret\n").unwrap();
+        fs.create_file("/root/skip/file").unwrap().write_all(b"data").unwrap();
+        fs.symlink("skip/file", "/root/l1").unwrap();
+        fs.symlink("dir/file", "/root/l2").unwrap();
+        fs.symlink("dir/secret", "/root/l3").unwrap();
+
+        let walker = |mode| Walker::new(&fs, &[71], mode)
+            .symlinks(SymlinkPolicy::Rewrite)
+            .creating_symlinks_with(create_symlink)
+            .exclude("skip".parse().unwrap());
+
+        walker(Mode::Encrypt).run(root).unwrap();
+
+        // "l1", "l2" and "l3" are "2B76", "2B75" and "2B74", and "dir/file" is "232E35/212E2B22".
+        assert_eq!(fs.read_link("/root/2B76").unwrap(), PathBuf::from("skip/file"));
+        assert_eq!(fs.read_link("/root/2B75").unwrap(), PathBuf::from("232E35/212E2B22"));
+        assert_eq!(fs.read_link("/root/2B74").unwrap(), PathBuf::from("232E35/secret"));
+        assert_eq!(read_file_contents(&fs, "/root/2B76"), b"data");
+        assert_eq!(read_file_contents(&fs, "/root/2B74"), b"data");
+
+        walker(Mode::Decrypt).run(root).unwrap();
+
+        assert_eq!(fs.read_link("/root/l1").unwrap(), PathBuf::from("skip/file"));
+        assert_eq!(fs.read_link("/root/l2").unwrap(), PathBuf::from("dir/file"));
+        assert_eq!(fs.read_link("/root/l3").unwrap(), PathBuf::from("dir/secret"));
+    }
+
+    #[test]
+    fn xor_directory_encrypt_mode_works() {
+
+        // Arrange.
+
+        // Use a single letter key 'G'
+        let key = vec![71];
+
+        // Make a filesystem as follows:
+        //
+        // parent_dir
+        //    |
+        //    +-- child_dir
+        //    |     |
+        //    |     +-- file_a
+        //    |     |
+        //    |     +-- file_b
+        //    |
+        //    +-- file_c
+        //
+
+        let fs = FS::new();
+        let file_a_contents_starting : [u8; 5] = [1_u8, 2_u8, 3_u8, 4_u8, 5_u8];
+        let file_b_contents_starting : [u8; 5] = [6_u8, 7_u8, 8_u8, 9_u8, 10_u8];
+        let file_c_contents_starting : [u8; 5] = [11_u8, 12_u8, 13_u8, 14_u8, 15_u8];
+        let file_a_contents_expected : [u8; 5] = [70_u8, 69_u8, 68_u8, 67_u8, 66_u8];
+        let file_b_contents_expected : [u8; 5] = [65_u8, 64_u8, 79_u8, 78_u8, 77_u8];
+        let file_c_contents_expected : [u8; 5] = [76_u8, 75_u8, 74_u8, 73_u8, 72_u8];
+
+        // Create the files
+        fs.new_dirbuilder()
+            .recursive(true)
+            .create("/parent_dir/child_dir")
+            .unwrap();
+
+        let mut file_a = fs.create_file("/parent_dir/child_dir/file_a").unwrap();
+        let mut file_b = fs.create_file("/parent_dir/child_dir/file_b").unwrap();
+        let mut file_c = fs.create_file("/parent_dir/file_c").unwrap();
+
+        // Write contents to files
+        file_a.write_all(&file_a_contents_starting).unwrap();
+        file_b.write_all(&file_b_contents_starting).unwrap();
+        file_c.write_all(&file_c_contents_starting).unwrap();
+
+        xor_dir(&fs, &"parent_dir", &key, &Mode::Encrypt);
+
+        assert!(fs.metadata("/37263522293318232E35").unwrap().is_dir());                                  // parent_dir -> 37263522293318232E35
+        assert!(fs.metadata("/37263522293318232E35/242F2E2B2318232E35").unwrap().is_dir());               // parent_dir/child_dir -> 37263522293318232E35/242F2E2B2318232E35
+        assert!(fs.metadata("/37263522293318232E35/242F2E2B2318232E35/212E2B221826").unwrap().is_file()); // parent_dir/child_dir/file_a -> 37263522293318232E35/242F2E2B2318232E35/212E2B221826
+        assert!(fs.metadata("/37263522293318232E35/242F2E2B2318232E35/212E2B221825").unwrap().is_file()); // parent_dir/child_dir/file_b -> 37263522293318232E35/242F2E2B2318232E35/212E2B221825
+        assert!(fs.metadata("/37263522293318232E35/212E2B221824").unwrap().is_file());                    // parent_dir/file_c -> 37263522293318232E35/212E2B221824
+
+        // Assert contents are XOR'd
+        let file_a_contents_actual : Vec<u8> = read_file_contents(&fs, "/37263522293318232E35/242F2E2B2318232E35/212E2B221826");
+        let file_b_contents_actual : Vec<u8> = read_file_contents(&fs, "/37263522293318232E35/242F2E2B2318232E35/212E2B221825");
+        let file_c_contents_actual : Vec<u8> = read_file_contents(&fs, "/37263522293318232E35/212E2B221824");
+
+        assert_eq!(file_a_contents_actual, file_a_contents_expected);
+        assert_eq!(file_b_contents_actual, file_b_contents_expected);
+        assert_eq!(file_c_contents_actual, file_c_contents_expected);
+    }
+
+}